@@ -0,0 +1,29 @@
+// Shows where the nonce would go in a commit, and what the commit looks like with a given value
+// of it, without mining anything:
+//
+//     git cat-file commit HEAD | cargo run --example commit_buffer -- 42
+
+use anyhow::Result;
+use git_power::{CommitBuffer, NonceLocation};
+use std::io::{Read, Write};
+
+fn main() -> Result<()> {
+    let value = match std::env::args().nth(1) {
+        Some(value) => value.parse()?,
+        None => 0,
+    };
+    let mut commit = Vec::new();
+    std::io::stdin().read_to_end(&mut commit)?;
+
+    let mut buf = CommitBuffer::new(&commit, NonceLocation::Header)?;
+    buf.write_nonce(value);
+    let range = buf.nonce_range();
+    eprintln!(
+        "The nonce takes up bytes {} to {} and reads {}",
+        range.start,
+        range.end,
+        String::from_utf8_lossy(buf.nonce())
+    );
+    std::io::stdout().write_all(buf.data())?;
+    Ok(())
+}
@@ -0,0 +1,35 @@
+// Mines a commit read from stdin until its id starts with the given number of zero bits, and
+// writes the mined object to stdout, ready for `git hash-object -t commit -w --stdin`:
+//
+//     git cat-file commit HEAD | cargo run --release --example mine -- 24
+
+use anyhow::Result;
+use git_power::pool::Pool;
+use git_power::repo::to_hex;
+use git_power::target::Target;
+use git_power::{run_pow, CommitBuffer, MineOptions, NoProgress, NonceLocation, Sha1};
+use std::io::{Read, Write};
+
+fn main() -> Result<()> {
+    let bits = match std::env::args().nth(1) {
+        Some(bits) => bits.parse()?,
+        None => 16,
+    };
+    let mut commit = Vec::new();
+    std::io::stdin().read_to_end(&mut commit)?;
+
+    let buf = CommitBuffer::new(&commit, NonceLocation::Header)?;
+    let target = Target {
+        bits,
+        ..Target::default()
+    };
+    let pool = Pool::new(num_cpus::get(), false)?;
+    let mined = run_pow::<Sha1>(&pool, buf, target, &MineOptions::default(), &NoProgress)?;
+    eprintln!(
+        "Found {} after {} attempts",
+        to_hex(&mined.hash),
+        mined.attempts
+    );
+    std::io::stdout().write_all(mined.commit.data())?;
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use crate::repo::to_hex;
+use crate::{encode_nonce, num_leading_zero_bits};
+use anyhow::{Error, Result};
+use std::path::Path;
+use std::time::Duration;
+
+// A record of how far a long search got, written every so often while mining, so that there's
+// something to go on if the process dies. It's a plain text file of `key value` lines:
+//
+//     seed 42
+//     position 1966080
+//     attempts 1843200
+//     seconds 10.002
+//     best 00000e3b...
+//     best-nonce AAAAAAAAAAAAAAAAAAAAAAAAAAAEBFOC
+//     leading-zeros 20
+//
+// The first `position` nonces, starting from wherever the seed has mining start, have been
+// handed out to the threads, and apart from the last block each thread took, they were tried.
+// The best hash is missing until one was found.
+
+pub struct Checkpoint<'a> {
+    pub seed: Option<u64>,
+    pub position: u128,
+    pub attempts: u128,
+    pub elapsed: Duration,
+    pub best: Option<(u128, &'a [u8])>,
+}
+
+impl Checkpoint<'_> {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut contents = String::new();
+        if let Some(seed) = self.seed {
+            contents.push_str(&format!("seed {}\n", seed));
+        }
+        contents.push_str(&format!(
+            "position {}\nattempts {}\nseconds {:.3}\n",
+            self.position,
+            self.attempts,
+            self.elapsed.as_secs_f64()
+        ));
+        if let Some((nonce, hash)) = self.best {
+            contents.push_str(&format!(
+                "best {}\nbest-nonce {}\nleading-zeros {}\n",
+                to_hex(hash),
+                String::from_utf8_lossy(&encode_nonce(nonce)),
+                num_leading_zero_bits(hash)
+            ));
+        }
+        // Going through a temporary file means the checkpoint is never seen half written
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        std::fs::write(&temp, contents)
+            .and_then(|_| std::fs::rename(&temp, path))
+            .map_err(|err| Error::msg(format!("Cannot write {}: {}", path.display(), err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn read(path: &Path) -> HashMap<String, String> {
+        let contents = std::fs::read_to_string(path).unwrap();
+        contents
+            .lines()
+            .map(|line| {
+                let (key, value) = line.split_once(' ').unwrap();
+                (key.to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    fn decode_nonce(nonce: &str) -> u128 {
+        nonce
+            .bytes()
+            .fold(0, |val, byte| val << 4 | (byte - b'A') as u128)
+    }
+
+    #[test]
+    fn checkpoints_read_back_as_written() {
+        let path =
+            std::env::temp_dir().join(format!("git-power-checkpoint-{}", std::process::id()));
+        let hash = [0x00, 0x00, 0x0e, 0x3b, 0xff];
+        let mut checkpoint = Checkpoint {
+            seed: Some(42),
+            position: 1966080,
+            attempts: 1843200,
+            elapsed: Duration::from_millis(10002),
+            best: Some((0xABCDEF, &hash)),
+        };
+        checkpoint.write(&path).unwrap();
+        let fields = read(&path);
+        assert_eq!(fields.len(), 7);
+        assert_eq!(fields["seed"], "42");
+        assert_eq!(fields["position"], "1966080");
+        assert_eq!(fields["attempts"], "1843200");
+        assert_eq!(fields["seconds"], "10.002");
+        assert_eq!(fields["best"], "00000e3bff");
+        assert_eq!(decode_nonce(&fields["best-nonce"]), 0xABCDEF);
+        assert_eq!(fields["leading-zeros"], "20");
+
+        // Written again over the last one, without a seed or a best hash yet
+        checkpoint.seed = None;
+        checkpoint.best = None;
+        checkpoint.write(&path).unwrap();
+        let fields = read(&path);
+        let mut keys: Vec<_> = fields.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["attempts", "position", "seconds"]);
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        assert!(!Path::new(&temp).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
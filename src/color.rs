@@ -0,0 +1,94 @@
+use crate::repo::to_hex;
+use std::io::{self, IsTerminal};
+
+// Highlighting the zeros a hash starts with, which is what all the work went into. Color is only
+// used when writing to a terminal, and never if `NO_COLOR` is set (see https://no-color.org).
+
+const ZEROS: &str = "\x1b[1;32m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether output to a stream should be colored, given that it is or isn't a terminal.
+pub fn enabled(is_terminal: bool) -> bool {
+    // An empty `NO_COLOR` counts as not being set
+    is_terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Whether to color what's printed to stdout.
+pub fn stdout() -> bool {
+    enabled(io::stdout().is_terminal())
+}
+
+/// Whether to color what's printed to stderr.
+pub fn stderr() -> bool {
+    enabled(io::stderr().is_terminal())
+}
+
+/// Renders a hash in hex, with the zero digits it starts with highlighted if `color` is set.
+pub fn hash(hash: &[u8], color: bool) -> String {
+    let hex = to_hex(hash);
+    let num_zeros = hex.len() - hex.trim_start_matches('0').len();
+    if !color || num_zeros == 0 {
+        return hex;
+    }
+    format!(
+        "{}{}{}{}",
+        ZEROS,
+        &hex[..num_zeros],
+        RESET,
+        &hex[num_zeros..]
+    )
+}
+
+/// The number of characters text takes up on the terminal, leaving out any color codes in it.
+pub fn width(text: &str) -> usize {
+    let mut width = 0;
+    let mut in_escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => in_escape = true,
+            'm' if in_escape => in_escape = false,
+            _ if !in_escape => width += 1,
+            _ => {}
+        }
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_leading_zeros_are_highlighted() {
+        let zeros_first = [0x00, 0x0a, 0xb0];
+        assert_eq!(hash(&zeros_first, false), "000ab0");
+        assert_eq!(hash(&zeros_first, true), "\x1b[1;32m000\x1b[0mab0");
+        // Nothing to highlight
+        assert_eq!(hash(&[0xab, 0x00], true), "ab00");
+    }
+
+    #[test]
+    fn color_codes_take_up_no_room() {
+        assert_eq!(width(""), 0);
+        assert_eq!(width("Found 000ab0"), 12);
+        assert_eq!(
+            width(&format!("Found {}", hash(&[0x00, 0x0a, 0xb0], true))),
+            12
+        );
+        // Counting characters rather than bytes
+        assert_eq!(width("~1.5 minutes élapsed"), 20);
+    }
+
+    #[test]
+    fn only_terminals_get_color_unless_no_color_is_set() {
+        // No other test looks at the variable, so changing it doesn't get in their way
+        std::env::remove_var("NO_COLOR");
+        assert!(enabled(true));
+        assert!(!enabled(false));
+        std::env::set_var("NO_COLOR", "1");
+        assert!(!enabled(true));
+        std::env::set_var("NO_COLOR", "");
+        assert!(enabled(true));
+        std::env::remove_var("NO_COLOR");
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{NonceLocation, OutputFormat};
+use anyhow::{Error, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Defaults for some of the options, read from a `.git-power.toml` in the home directory and at
+// the root of the repository, the latter taking precedence. Only as much of TOML is understood
+// as these need: `key = value` lines, with strings in double quotes, and `#` comments. The
+// environment variables in `ENV_VARS` take precedence over both files.
+
+const FILE_NAME: &str = ".git-power.toml";
+
+// The environment variables that can be set, along with the keys they stand in for
+const ENV_VARS: [(&str, &str); 2] = [("GIT_POWER_BITS", "bits"), ("GIT_POWER_THREADS", "threads")];
+
+#[derive(Default)]
+pub struct Defaults {
+    pub bits: Option<u16>,
+    pub threads: Option<u8>,
+    pub nonce_location: Option<NonceLocation>,
+    pub format: Option<OutputFormat>,
+}
+
+/// Reads the config files there are, with the one in the repository overriding the one in
+/// the home directory, and then the environment variables.
+pub fn load() -> Result<Defaults> {
+    let mut defaults = Defaults::default();
+    let home = std::env::var_os("HOME").map(|home| Path::new(&home).join(FILE_NAME));
+    let repo = repo_root().map(|root| root.join(FILE_NAME));
+    // The home directory might well be a repository itself
+    let repo = repo.filter(|repo| Some(repo) != home.as_ref());
+    for path in home.iter().chain(repo.iter()) {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(Error::msg(format!(
+                    "Cannot read {}: {}",
+                    path.display(),
+                    err
+                )))
+            }
+        };
+        for (i, line) in contents.lines().enumerate() {
+            parse_line(line, &mut defaults)
+                .map_err(|err| Error::msg(format!("{}:{}: {}", path.display(), i + 1, err)))?;
+        }
+    }
+    for (var, key) in ENV_VARS {
+        if let Some(value) = std::env::var_os(var) {
+            let value = value.to_string_lossy();
+            set(key, &value, &mut defaults)
+                .map_err(|err| Error::msg(format!("{}: {}", var, err)))?;
+        }
+    }
+    Ok(defaults)
+}
+
+/// Finds the top of the working tree of the repository being mined. git knows best, taking
+/// `GIT_DIR`, `GIT_WORK_TREE` and `core.worktree` into account just like for the repository
+/// itself. Bare repositories have no working tree, and so no config file there.
+fn repo_root() -> Option<PathBuf> {
+    match Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .stderr(Stdio::null())
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            let root = String::from_utf8_lossy(&output.stdout);
+            Some(PathBuf::from(root.trim_end_matches('\n')))
+        }
+        Ok(_) => None,
+        // Without git to ask, go by the environment the way git would
+        Err(_) => {
+            if let Some(work_tree) = std::env::var_os("GIT_WORK_TREE") {
+                return Some(PathBuf::from(work_tree));
+            }
+            let cwd = std::env::current_dir().ok()?;
+            // With only `GIT_DIR`, git takes the current directory to be the working tree
+            if std::env::var_os("GIT_DIR").is_some() {
+                return Some(cwd);
+            }
+            cwd.ancestors()
+                .find(|dir| dir.join(".git").exists())
+                .map(Path::to_path_buf)
+        }
+    }
+}
+
+fn parse_line(line: &str, defaults: &mut Defaults) -> Result<()> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(());
+    }
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| Error::msg("Expected a line of the form key = value"))?;
+    set(key.trim(), parse_value(value.trim())?, defaults)
+}
+
+fn set(key: &str, value: &str, defaults: &mut Defaults) -> Result<()> {
+    let invalid = |_| Error::msg(format!("Invalid value for {}: {}", key, value));
+    match key {
+        "bits" => defaults.bits = Some(value.parse().map_err(invalid)?),
+        "threads" => match value.parse().map_err(invalid)? {
+            0 => return Err(Error::msg("threads must be at least 1")),
+            threads => defaults.threads = Some(threads),
+        },
+        "nonce-location" => defaults.nonce_location = Some(value.parse()?),
+        "format" => defaults.format = Some(value.parse()?),
+        key => return Err(Error::msg(format!("Unknown key: {}", key))),
+    }
+    Ok(())
+}
+
+/// Strips a value of its quotes, if it's a string, and of a trailing comment.
+fn parse_value(value: &str) -> Result<&str> {
+    match value.strip_prefix('"') {
+        Some(rest) => {
+            let end = rest
+                .find('"')
+                .ok_or_else(|| Error::msg("Unterminated string"))?;
+            let after = rest[end + 1..].trim();
+            if !after.is_empty() && !after.starts_with('#') {
+                return Err(Error::msg(format!("Unexpected {} after string", after)));
+            }
+            Ok(&rest[..end])
+        }
+        None => Ok(value.split('#').next().unwrap_or_default().trim()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Defaults> {
+        let mut defaults = Defaults::default();
+        for line in contents.lines() {
+            parse_line(line, &mut defaults)?;
+        }
+        Ok(defaults)
+    }
+
+    fn error(contents: &str) -> String {
+        parse(contents).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn reads_every_key() {
+        let defaults = parse(
+            "# Mining defaults\n\
+             \n\
+             bits = 24\n\
+             threads=4 # one per core\n\
+             nonce-location = \"trailer\"\n\
+             format = \"json\" # for scripts\n",
+        )
+        .unwrap();
+        assert_eq!(defaults.bits, Some(24));
+        assert_eq!(defaults.threads, Some(4));
+        assert!(defaults.nonce_location == Some(NonceLocation::Trailer));
+        assert!(defaults.format == Some(OutputFormat::Json));
+    }
+
+    #[test]
+    fn later_lines_win() {
+        assert_eq!(parse("bits = 8\nbits = 12").unwrap().bits, Some(12));
+        assert_eq!(parse("").unwrap().bits, None);
+    }
+
+    #[test]
+    fn strings_keep_what_looks_like_a_comment() {
+        assert_eq!(parse_value("\"a # b\" # c").unwrap(), "a # b");
+        assert_eq!(parse_value("8 # c").unwrap(), "8");
+    }
+
+    #[test]
+    fn unknown_keys_are_errors() {
+        assert_eq!(error("zeros = 8"), "Unknown key: zeros");
+        assert_eq!(error("bits"), "Expected a line of the form key = value");
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        assert_eq!(error("bits = many"), "Invalid value for bits: many");
+        assert_eq!(error("bits = -1"), "Invalid value for bits: -1");
+        assert_eq!(error("threads = 256"), "Invalid value for threads: 256");
+        assert_eq!(error("threads = 0"), "threads must be at least 1");
+        assert_eq!(error("format = \"json"), "Unterminated string");
+        assert_eq!(error("format = \"json\" x"), "Unexpected x after string");
+        assert!(parse("nonce-location = \"footer\"").is_err());
+        assert!(parse("format = \"xml\"").is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use crate::gpu::Gpu;
+use crate::hasher::Sha1Midstate;
+use anyhow::{Error, Result};
+use std::ffi::{c_char, c_void, CString};
+use std::ptr;
+
+// Mining on NVIDIA GPUs through CUDA. The kernel is compiled at runtime with NVRTC and then
+// loaded through the driver API, so only the libraries coming with the driver and the CUDA
+// toolkit are needed, and not the CUDA compiler itself. Like for OpenCL, the few functions we
+// need are declared by hand.
+
+const PRELUDE: &str = "\
+typedef unsigned int uint;
+typedef unsigned long long ulong;
+#define KERNEL extern \"C\" __global__
+#define GLOBAL
+#define GLOBAL_ID() (blockIdx.x * blockDim.x + threadIdx.x)
+#define ROTL(x, n) __funnelshift_l((x), (x), (n))
+#define CLZ(x) __clz(x)
+#define ATOMIC_CMPXCHG(p, cmp, val) atomicCAS((p), (cmp), (val))
+";
+
+// Number of threads of each block the kernel is launched with
+const BLOCK_SIZE: usize = 256;
+
+type CuResult = i32;
+type Handle = *mut c_void;
+type DevicePtr = u64;
+
+const CUDA_SUCCESS: CuResult = 0;
+const NVRTC_SUCCESS: i32 = 0;
+
+#[link(name = "cuda")]
+extern "C" {
+    fn cuInit(flags: u32) -> CuResult;
+    fn cuDeviceGet(device: *mut i32, ordinal: i32) -> CuResult;
+    fn cuDeviceGetName(name: *mut c_char, len: i32, device: i32) -> CuResult;
+    fn cuCtxCreate_v2(context: *mut Handle, flags: u32, device: i32) -> CuResult;
+    fn cuCtxDestroy_v2(context: Handle) -> CuResult;
+    fn cuCtxSynchronize() -> CuResult;
+    fn cuModuleLoadData(module: *mut Handle, image: *const c_void) -> CuResult;
+    fn cuModuleUnload(module: Handle) -> CuResult;
+    fn cuModuleGetFunction(function: *mut Handle, module: Handle, name: *const c_char) -> CuResult;
+    fn cuMemAlloc_v2(ptr: *mut DevicePtr, size: usize) -> CuResult;
+    fn cuMemFree_v2(ptr: DevicePtr) -> CuResult;
+    fn cuMemcpyHtoD_v2(dst: DevicePtr, src: *const c_void, size: usize) -> CuResult;
+    fn cuMemcpyDtoH_v2(dst: *mut c_void, src: DevicePtr, size: usize) -> CuResult;
+    fn cuLaunchKernel(
+        function: Handle,
+        grid_x: u32,
+        grid_y: u32,
+        grid_z: u32,
+        block_x: u32,
+        block_y: u32,
+        block_z: u32,
+        shared_mem: u32,
+        stream: Handle,
+        params: *mut *mut c_void,
+        extra: *mut *mut c_void,
+    ) -> CuResult;
+}
+
+#[link(name = "nvrtc")]
+extern "C" {
+    fn nvrtcCreateProgram(
+        program: *mut Handle,
+        source: *const c_char,
+        name: *const c_char,
+        num_headers: i32,
+        headers: *const *const c_char,
+        include_names: *const *const c_char,
+    ) -> i32;
+    fn nvrtcCompileProgram(program: Handle, num_options: i32, options: *const *const c_char)
+        -> i32;
+    fn nvrtcGetProgramLogSize(program: Handle, size: *mut usize) -> i32;
+    fn nvrtcGetProgramLog(program: Handle, log: *mut c_char) -> i32;
+    fn nvrtcGetPTXSize(program: Handle, size: *mut usize) -> i32;
+    fn nvrtcGetPTX(program: Handle, ptx: *mut c_char) -> i32;
+    fn nvrtcDestroyProgram(program: *mut Handle) -> i32;
+}
+
+fn check(err: CuResult, what: &str) -> Result<()> {
+    if err == CUDA_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::msg(format!("CUDA error {} while {}", err, what)))
+    }
+}
+
+/// A CUDA device set up to search for nonces of one particular commit.
+pub struct Cuda {
+    name: String,
+    context: Handle,
+    module: Handle,
+    function: Handle,
+    buffers: Vec<DevicePtr>,
+    midstate: DevicePtr,
+    tail: DevicePtr,
+    result: DevicePtr,
+    num_blocks: u32,
+    nonce_offset: u32,
+    bits: u32,
+}
+
+impl Cuda {
+    /// Picks the first CUDA device, compiles the kernel for it, and uploads the midstate of
+    /// the commit to it.
+    pub fn new(midstate: &Sha1Midstate, bits: u16) -> Result<Self> {
+        let ptx = compile()?;
+        unsafe {
+            check(cuInit(0), "initializing")?;
+            let mut device = 0;
+            check(cuDeviceGet(&mut device, 0), "looking for a device")?;
+            let mut name = [0u8; 256];
+            check(
+                cuDeviceGetName(name.as_mut_ptr() as *mut c_char, name.len() as i32, device),
+                "querying the device name",
+            )?;
+            let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+            let mut context = ptr::null_mut();
+            check(
+                cuCtxCreate_v2(&mut context, 0, device),
+                "creating a context",
+            )?;
+            // From here on, whatever was created so far is released when `gpu` is dropped
+            let mut gpu = Cuda {
+                name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+                context,
+                module: ptr::null_mut(),
+                function: ptr::null_mut(),
+                buffers: Vec::new(),
+                midstate: 0,
+                tail: 0,
+                result: 0,
+                num_blocks: 0,
+                nonce_offset: midstate.nonce_offset as u32,
+                bits: bits as u32,
+            };
+            check(
+                cuModuleLoadData(&mut gpu.module, ptx.as_ptr() as *const c_void),
+                "loading the kernel",
+            )?;
+            check(
+                cuModuleGetFunction(
+                    &mut gpu.function,
+                    gpu.module,
+                    b"search\0".as_ptr() as *const c_char,
+                ),
+                "looking up the kernel",
+            )?;
+
+            // The tail is hashed as big-endian words, so we convert it once up front
+            let words: Vec<u32> = midstate
+                .template
+                .chunks_exact(4)
+                .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+                .collect();
+            gpu.num_blocks = (words.len() / 16) as u32;
+            gpu.midstate = gpu.create_buffer(&midstate.h)?;
+            gpu.tail = gpu.create_buffer(&words)?;
+            gpu.result = gpu.create_buffer(&[0u32; 2])?;
+            Ok(gpu)
+        }
+    }
+
+    unsafe fn create_buffer(&mut self, data: &[u32]) -> Result<DevicePtr> {
+        let size = std::mem::size_of_val(data);
+        let mut buffer = 0;
+        check(cuMemAlloc_v2(&mut buffer, size), "allocating a buffer")?;
+        self.buffers.push(buffer);
+        check(
+            cuMemcpyHtoD_v2(buffer, data.as_ptr() as *const c_void, size),
+            "copying to a buffer",
+        )?;
+        Ok(buffer)
+    }
+}
+
+/// Compiles the kernel to PTX, which the driver then translates for the device at hand.
+fn compile() -> Result<Vec<u8>> {
+    let source = CString::new(format!("{}{}", PRELUDE, include_str!("sha1.cl"))).unwrap();
+    unsafe {
+        let mut program = ptr::null_mut();
+        if nvrtcCreateProgram(
+            &mut program,
+            source.as_ptr(),
+            b"sha1.cu\0".as_ptr() as *const c_char,
+            0,
+            ptr::null(),
+            ptr::null(),
+        ) != NVRTC_SUCCESS
+        {
+            return Err(Error::msg("Failed to create the CUDA program"));
+        }
+        let result = if nvrtcCompileProgram(program, 0, ptr::null()) != NVRTC_SUCCESS {
+            let mut log_size = 0;
+            nvrtcGetProgramLogSize(program, &mut log_size);
+            let mut log = vec![0u8; log_size];
+            nvrtcGetProgramLog(program, log.as_mut_ptr() as *mut c_char);
+            let log_len = log.iter().position(|&c| c == 0).unwrap_or(log.len());
+            Err(Error::msg(format!(
+                "Failed to build the CUDA kernel:\n{}",
+                String::from_utf8_lossy(&log[..log_len])
+            )))
+        } else {
+            let mut ptx_size = 0;
+            nvrtcGetPTXSize(program, &mut ptx_size);
+            let mut ptx = vec![0u8; ptx_size];
+            nvrtcGetPTX(program, ptx.as_mut_ptr() as *mut c_char);
+            Ok(ptx)
+        };
+        nvrtcDestroyProgram(&mut program);
+        result
+    }
+}
+
+impl Gpu for Cuda {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn search(&mut self, base: u128, count: usize) -> Result<Option<u128>> {
+        unsafe {
+            let mut result = [0u32; 2];
+            let size = std::mem::size_of_val(&result);
+            check(
+                cuMemcpyHtoD_v2(self.result, result.as_ptr() as *const c_void, size),
+                "resetting the result",
+            )?;
+            let mut base_lo = base as u64;
+            let mut base_hi = (base >> 64) as u64;
+            let mut params = [
+                &mut self.midstate as *mut DevicePtr as *mut c_void,
+                &mut self.tail as *mut DevicePtr as *mut c_void,
+                &mut self.num_blocks as *mut u32 as *mut c_void,
+                &mut self.nonce_offset as *mut u32 as *mut c_void,
+                &mut base_lo as *mut u64 as *mut c_void,
+                &mut base_hi as *mut u64 as *mut c_void,
+                &mut self.bits as *mut u32 as *mut c_void,
+                &mut self.result as *mut DevicePtr as *mut c_void,
+            ];
+            check(
+                cuLaunchKernel(
+                    self.function,
+                    count.div_ceil(BLOCK_SIZE) as u32,
+                    1,
+                    1,
+                    BLOCK_SIZE as u32,
+                    1,
+                    1,
+                    0,
+                    ptr::null_mut(),
+                    params.as_mut_ptr(),
+                    ptr::null_mut(),
+                ),
+                "running the kernel",
+            )?;
+            check(cuCtxSynchronize(), "waiting for the kernel")?;
+            check(
+                cuMemcpyDtoH_v2(result.as_mut_ptr() as *mut c_void, self.result, size),
+                "reading the result",
+            )?;
+            Ok((result[0] != 0).then(|| base.wrapping_add(result[1] as u128)))
+        }
+    }
+}
+
+impl Drop for Cuda {
+    fn drop(&mut self) {
+        unsafe {
+            for &buffer in &self.buffers {
+                cuMemFree_v2(buffer);
+            }
+            if !self.module.is_null() {
+                cuModuleUnload(self.module);
+            }
+            cuCtxDestroy_v2(self.context);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// What the unit tests mine, shared between the modules that have tests mining something.
+
+use crate::target::Target;
+use crate::{CommitBuffer, NonceLocation};
+
+/// An empty root commit, as git writes it.
+pub const COMMIT: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+    author A U Thor <author@example.com> 1700000000 +0000\n\
+    committer A U Thor <author@example.com> 1700000000 +0000\n\
+    \n\
+    Initial commit\n";
+
+/// `COMMIT`, ready to be mined with a nonce header.
+pub fn commit() -> CommitBuffer {
+    CommitBuffer::new(COMMIT, NonceLocation::Header).unwrap()
+}
+
+/// A target of `bits` leading zero bits and nothing else.
+pub fn bits(bits: u16) -> Target {
+    Target {
+        bits,
+        ..Target::default()
+    }
+}
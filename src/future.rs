@@ -0,0 +1,216 @@
+use crate::hasher::PowHasher;
+use crate::pool::Pool;
+use crate::target::Target;
+use crate::{run_pow, CommitBuffer, MineOptions, Mined, ProgressSink, Stats};
+use anyhow::{Error, Result};
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+// An async front to `run_pow`, for mining from within async code without holding up the
+// executor. Mining blocks the thread it runs on while waiting for the workers, so it gets a
+// thread of its own, and the futures here merely wait on it. They only rely on being woken up
+// like any future does, so they work with whatever executor there is.
+
+/// A hash closer to the target than any before it, along with its number of leading zero bits.
+pub type Improvement = (Vec<u8>, u16);
+
+/// Starts mining the commit on the threads of `pool`, without waiting for it. Mining goes on
+/// whether or not the returned halves are polled, until it's done or the `Mining` half is
+/// dropped, which cancels it by way of `options.cancel`. That's replaced with a flag of the
+/// future's own.
+pub fn run_pow_async<H: PowHasher + 'static>(
+    pool: Pool,
+    commit: CommitBuffer,
+    target: Target,
+    mut options: MineOptions,
+) -> (Mining, Improvements) {
+    let result = Arc::new(Channel::new());
+    let improvements = Arc::new(Channel::new());
+    let cancel = Arc::new(AtomicBool::new(false));
+    options.cancel = Some(Arc::clone(&cancel));
+    {
+        let result = Sender(Arc::clone(&result));
+        let sink = ChannelSink(Sender(Arc::clone(&improvements)));
+        std::thread::spawn(move || {
+            let outcome = run_pow::<H>(&pool, commit, target, &options, &sink);
+            // The improvements only end once the workers are gone, so that waiting for them
+            // to end is a way to wait for mining to wind down after cancelling it
+            drop(pool);
+            drop(sink);
+            result.send(outcome);
+        });
+    }
+    (Mining { result, cancel }, Improvements(improvements))
+}
+
+/// The outcome of mining started by [`run_pow_async`].
+pub struct Mining {
+    result: Arc<Channel<Result<Mined>>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Drop for Mining {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Future for Mining {
+    type Output = Result<Mined>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.result.poll_recv(cx).map(|result| {
+            result.unwrap_or_else(|| Err(Error::msg("Mining stopped without a result")))
+        })
+    }
+}
+
+/// The hashes coming closer to the target, as mining started by [`run_pow_async`] finds them.
+pub struct Improvements(Arc<Channel<Improvement>>);
+
+impl Improvements {
+    /// Waits for the next improvement, which is `None` once mining is over.
+    pub async fn next(&mut self) -> Option<Improvement> {
+        poll_fn(|cx| self.0.poll_recv(cx)).await
+    }
+}
+
+struct ChannelSink(Sender<Improvement>);
+
+impl ProgressSink for ChannelSink {
+    fn on_improvement(&self, hash: &[u8], zeros: u16) {
+        self.0.send((hash.to_vec(), zeros));
+    }
+
+    // The improvements end once the sink is dropped, which also covers mining failing
+    fn on_done(&self, _: &Stats) {}
+}
+
+/// The sending end of a channel, which closes it when dropped, so that the receiving end isn't
+/// left waiting even if the mining thread panics.
+struct Sender<T>(Arc<Channel<T>>);
+
+impl<T> Sender<T> {
+    fn send(&self, item: T) {
+        self.0.send(item);
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// A queue between a thread and a task, with the task being woken up once there's something to
+/// take from it.
+struct Channel<T>(Mutex<State<T>>);
+
+struct State<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<T> Channel<T> {
+    fn new() -> Self {
+        Self(Mutex::new(State {
+            items: VecDeque::new(),
+            closed: false,
+            waker: None,
+        }))
+    }
+
+    fn send(&self, item: T) {
+        let mut state = self.0.lock().unwrap();
+        state.items.push_back(item);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn poll_recv(&self, cx: &mut Context) -> Poll<Option<T>> {
+        let mut state = self.0.lock().unwrap();
+        match state.items.pop_front() {
+            Some(item) => Poll::Ready(Some(item)),
+            None if state.closed => Poll::Ready(None),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{bits, commit};
+    use crate::Sha1;
+    use std::task::Wake;
+    use std::thread::Thread;
+    use std::time::{Duration, Instant};
+
+    // Just enough of an executor to run a future to completion on the current thread
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Arc::new(Unpark(std::thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+
+    #[test]
+    fn mines_with_improvements() {
+        let pool = Pool::new(2, false).unwrap();
+        let (mining, mut improvements) =
+            run_pow_async::<Sha1>(pool, commit(), bits(12), MineOptions::default());
+        let mined = block_on(mining).unwrap();
+        assert!(crate::num_leading_zero_bits(&mined.hash) >= 12);
+        let mut last = None;
+        while let Some((hash, zeros)) = block_on(improvements.next()) {
+            assert!(last < Some(zeros));
+            last = Some(zeros);
+            assert_eq!(crate::num_leading_zero_bits(&hash), zeros);
+        }
+        assert!(last >= Some(12));
+    }
+
+    #[test]
+    fn dropping_the_future_stops_mining() {
+        let pool = Pool::new(2, false).unwrap();
+        // Without a time limit, this would go on practically forever
+        let (mining, mut improvements) =
+            run_pow_async::<Sha1>(pool, commit(), bits(160), MineOptions::default());
+        assert!(block_on(improvements.next()).is_some());
+        let start = Instant::now();
+        drop(mining);
+        // The improvements end once the workers have exited
+        while block_on(improvements.next()).is_some() {}
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::hasher::Sha1Midstate;
+use anyhow::{Error, Result};
+
+// Mining on GPUs. Only the search through the nonces runs on the device: the commit buffer and
+// its midstate are prepared on the CPU as usual, and the kernel in sha1.cl picks up hashing
+// from there. The same kernel is used by every backend, each of which is behind a feature of
+// its own.
+
+/// A device set up to search for nonces of one particular commit.
+pub trait Gpu {
+    fn name(&self) -> &str;
+
+    /// Hashes `count` nonces starting at `base`, returning one of them that meets the target
+    /// if there is any.
+    fn search(&mut self, base: u128, count: usize) -> Result<Option<u128>>;
+}
+
+/// Sets up the first device found by any of the backends that were built.
+pub fn open(midstate: &Sha1Midstate, bits: u16) -> Result<Box<dyn Gpu>> {
+    let mut errors = Vec::new();
+    #[cfg(feature = "cuda")]
+    match crate::cuda::Cuda::new(midstate, bits) {
+        Ok(gpu) => return Ok(Box::new(gpu)),
+        Err(err) => errors.push(format!("CUDA: {}", err)),
+    }
+    #[cfg(feature = "opencl")]
+    match crate::opencl::OpenCl::new(midstate, bits) {
+        Ok(gpu) => return Ok(Box::new(gpu)),
+        Err(err) => errors.push(format!("OpenCL: {}", err)),
+    }
+    Err(Error::msg(format!(
+        "No GPU could be used:\n{}",
+        errors.join("\n")
+    )))
+}
+
+// These only run where there's a device to run them on, and pass trivially everywhere else
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::commit;
+    use crate::pool::Pool;
+    use crate::target::Target;
+    use crate::{
+        encode_nonce, num_leading_zero_bits, run_pow, run_pow_gpu, MineOptions, NoProgress,
+        PowHasher, Sha1,
+    };
+    use sha1::Digest;
+
+    /// A device of every backend there is one for, rather than only the first one `open`
+    /// comes up with.
+    fn devices(midstate: &Sha1Midstate, bits: u16) -> Vec<Box<dyn Gpu>> {
+        let mut devices: Vec<Box<dyn Gpu>> = Vec::new();
+        #[cfg(feature = "cuda")]
+        match crate::cuda::Cuda::new(midstate, bits) {
+            Ok(gpu) => devices.push(Box::new(gpu)),
+            Err(err) => eprintln!("Skipping CUDA, since there's no device: {}", err),
+        }
+        #[cfg(feature = "opencl")]
+        match crate::opencl::OpenCl::new(midstate, bits) {
+            Ok(gpu) => devices.push(Box::new(gpu)),
+            Err(err) => eprintln!("Skipping OpenCL, since there's no device: {}", err),
+        }
+        devices
+    }
+
+    #[test]
+    fn finds_the_nonces_the_cpu_finds() {
+        const BITS: u16 = 6;
+        // A multiple of the block size of every backend, so that no nonce outside of the
+        // range gets searched
+        const COUNT: usize = 1 << 12;
+        let buf = commit();
+        let midstate = buf.midstate::<Sha1>();
+        for mut gpu in devices(&midstate, BITS) {
+            // The second range has the nonces carry over from the lower 64 bits into the upper
+            for base in [0, u64::MAX as u128 - COUNT as u128 / 2] {
+                let matching: Vec<u128> = (base..base + COUNT as u128)
+                    .filter(|&nonce| {
+                        let hash = Sha1::finish(&mut midstate.clone(), &encode_nonce(nonce));
+                        num_leading_zero_bits(&hash) >= BITS
+                    })
+                    .collect();
+                match gpu.search(base, COUNT).unwrap() {
+                    Some(nonce) => {
+                        assert!(matching.contains(&nonce), "{} on {}", nonce, gpu.name())
+                    }
+                    None => assert!(matching.is_empty(), "{:?} on {}", matching, gpu.name()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mines_like_the_cpu() {
+        let target = Target {
+            bits: 12,
+            ..Target::default()
+        };
+        let buf = commit();
+        if devices(&buf.midstate::<Sha1>(), target.bits).is_empty() {
+            return;
+        }
+        let options = MineOptions {
+            seed: Some(1),
+            ..MineOptions::default()
+        };
+        let gpu = run_pow_gpu(buf.clone(), target.clone(), &options, &NoProgress).unwrap();
+        let pool = Pool::new(1, false).unwrap();
+        let cpu = run_pow::<Sha1>(&pool, buf, target, &options, &NoProgress).unwrap();
+        for mined in [&gpu, &cpu] {
+            let data = mined.commit.data();
+            let mut object = format!("commit {}\0", data.len()).into_bytes();
+            object.extend_from_slice(data);
+            assert_eq!(mined.hash, Sha1::digest(&object).to_vec());
+            assert!(num_leading_zero_bits(&mined.hash) >= 12);
+        }
+        // The GPU goes with whichever hash in a batch meeting the target is found first rather
+        // than the lowest nonce, so the nonces can differ, but nothing else about the commits
+        assert_eq!(gpu.commit.data().len(), cpu.commit.data().len());
+        let nonce = gpu.commit.nonce_range();
+        assert_eq!(nonce, cpu.commit.nonce_range());
+        assert_eq!(
+            gpu.commit.data()[..nonce.start],
+            cpu.commit.data()[..nonce.start]
+        );
+        assert_eq!(
+            gpu.commit.data()[nonce.end..],
+            cpu.commit.data()[nonce.end..]
+        );
+    }
+}
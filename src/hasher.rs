@@ -0,0 +1,441 @@
+use crate::sha256::Sha256;
+use anyhow::{Error, Result};
+use sha1::digest::consts::{U20, U32};
+use sha1::digest::generic_array::GenericArray;
+use sha1::Sha1;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// How many hashes are computed together by `PowHasher::finish_lanes`.
+pub const LANES: usize = 8;
+
+/// A hash algorithm that commits can be mined with. Since only the nonce changes between
+/// attempts, the state after hashing everything up to the nonce is computed once as a
+/// midstate, along with the bytes following it, and each attempt only has to fill in the
+/// nonce and finish hashing from there.
+pub trait PowHasher {
+    /// Width of the digest, in bits.
+    const OUTPUT_BITS: u16;
+
+    /// The hash state after consuming the immutable prefix of the commit, along with
+    /// whatever else is needed to finish hashing it with a given nonce.
+    type State: Clone + Send + Sync;
+
+    /// The finished digest.
+    type Output: AsRef<[u8]>;
+
+    /// Name of the implementation doing the hashing.
+    fn backend() -> String;
+
+    /// Prepares for hashing `buf` with different values of the nonce spanning `nonce`.
+    fn midstate(buf: &[u8], nonce: Range<usize>) -> Self::State;
+
+    fn finish(state: &mut Self::State, nonce: &[u8]) -> Self::Output;
+
+    /// Finishes a hash for each of several nonces. Implementations can hash them in parallel
+    /// using SIMD, and the default just hashes them one after another.
+    fn finish_lanes(state: &mut Self::State, nonces: [&[u8]; LANES]) -> [Self::Output; LANES] {
+        nonces.map(|nonce| Self::finish(state, nonce))
+    }
+}
+
+/// The SHA-1 state after every whole block preceding the nonce, and the rest of the commit
+/// starting at the block the nonce is in. Only the blocks of the template have to be hashed
+/// for each attempt, with the nonce written to it at a known offset.
+#[derive(Clone)]
+pub struct Sha1Midstate {
+    pub h: [u32; 5],
+    pub template: Vec<u8>,
+    pub nonce_offset: usize,
+    backend: Sha1Backend,
+}
+
+/// The ways SHA-1 can be computed, from fastest to slowest. The fastest one the CPU supports
+/// is used, unless another one is selected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sha1Backend {
+    /// The SHA extensions, through the sha-1 crate.
+    ShaNi,
+    /// Eight hashes at once with 256-bit vectors.
+    Avx2,
+    /// Four hashes at once with 128-bit vectors.
+    Sse2,
+    /// One hash at a time, without any special instructions.
+    Scalar,
+}
+
+static SHA1_BACKEND: OnceLock<Sha1Backend> = OnceLock::new();
+
+impl Sha1Backend {
+    const ALL: [Sha1Backend; 4] = [Self::ShaNi, Self::Avx2, Self::Sse2, Self::Scalar];
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Self::ShaNi => is_x86_feature_detected!("sha"),
+            #[cfg(target_arch = "x86_64")]
+            Self::Avx2 => crate::sha1x::avx2::is_supported(),
+            #[cfg(target_arch = "x86_64")]
+            Self::Sse2 => crate::sha1x::sse2::is_supported(),
+            Self::Scalar => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    /// The backend in use, detected the first time it's needed.
+    pub fn current() -> Self {
+        *SHA1_BACKEND.get_or_init(|| {
+            Self::ALL
+                .iter()
+                .cloned()
+                .find(|backend| backend.is_supported())
+                .unwrap()
+        })
+    }
+
+    /// Uses the given backend from now on instead of detecting one. This has to happen
+    /// before anything is hashed.
+    pub fn select(self) -> Result<()> {
+        if !self.is_supported() {
+            return Err(Error::msg(format!("This CPU doesn't support {}", self)));
+        }
+        SHA1_BACKEND
+            .set(self)
+            .map_err(|_| Error::msg("The SHA-1 backend was already chosen"))
+    }
+}
+
+impl FromStr for Sha1Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "sha-ni" => Self::ShaNi,
+            "avx2" => Self::Avx2,
+            "sse2" => Self::Sse2,
+            "scalar" => Self::Scalar,
+            _ => return Err(Error::msg(format!("Unknown SHA-1 backend {}", s))),
+        })
+    }
+}
+
+impl fmt::Display for Sha1Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::ShaNi => "SHA-NI",
+            Self::Avx2 => "AVX2",
+            Self::Sse2 => "SSE2",
+            Self::Scalar => "scalar",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl PowHasher for Sha1 {
+    const OUTPUT_BITS: u16 = 160;
+    type State = Sha1Midstate;
+    type Output = GenericArray<u8, U20>;
+
+    fn backend() -> String {
+        Sha1Backend::current().to_string()
+    }
+
+    fn midstate(buf: &[u8], nonce: Range<usize>) -> Self::State {
+        let mut h = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+        let block_start = nonce.start / 64 * 64;
+        for block in buf[..block_start].chunks_exact(64) {
+            sha1::compress(&mut h, &[*GenericArray::from_slice(block)]);
+        }
+        Sha1Midstate {
+            h,
+            template: padded_template(buf, block_start),
+            nonce_offset: nonce.start - block_start,
+            backend: Sha1Backend::current(),
+        }
+    }
+
+    fn finish(state: &mut Self::State, nonce: &[u8]) -> Self::Output {
+        let nonce_offset = state.nonce_offset;
+        state.template[nonce_offset..nonce_offset + nonce.len()].copy_from_slice(nonce);
+
+        let mut h = state.h;
+        for block in state.template.as_chunks::<64>().0 {
+            if state.backend == Sha1Backend::Scalar {
+                crate::sha1x::compress(&mut h, block);
+            } else {
+                sha1::compress(&mut h, &[*GenericArray::from_slice(block)]);
+            }
+        }
+        to_output(h)
+    }
+
+    fn finish_lanes(state: &mut Self::State, nonces: [&[u8]; LANES]) -> [Self::Output; LANES] {
+        // Safe since the backend is only ever one the CPU supports
+        #[cfg(target_arch = "x86_64")]
+        match state.backend {
+            Sha1Backend::Avx2 => {
+                return finish_simd(state, nonces, crate::sha1x::avx2::compress);
+            }
+            Sha1Backend::Sse2 => {
+                // Two batches of four lanes each
+                let mut out = [GenericArray::default(); LANES];
+                for (out, nonces) in out.chunks_exact_mut(4).zip(nonces.chunks_exact(4)) {
+                    let nonces = std::array::from_fn(|i| nonces[i]);
+                    out.copy_from_slice(&finish_simd(state, nonces, crate::sha1x::sse2::compress));
+                }
+                return out;
+            }
+            _ => {}
+        }
+        nonces.map(|nonce| Self::finish(state, nonce))
+    }
+}
+
+/// The part of `buf` starting at `block_start`, padded the way both SHA-1 and SHA-256 expect.
+/// Since the nonce has a fixed length, so does the message, which means the padding is also
+/// the same for every attempt.
+fn padded_template(buf: &[u8], block_start: usize) -> Vec<u8> {
+    // The message ends with a 1 bit, then zeros up until 8 bytes short of a whole block,
+    // which hold the length of the message in bits
+    let mut template = buf[block_start..].to_vec();
+    template.push(0x80);
+    template.resize((template.len() + 8).div_ceil(64) * 64 - 8, 0);
+    template.extend_from_slice(&(8 * buf.len() as u64).to_be_bytes());
+    template
+}
+
+fn to_output(h: [u32; 5]) -> GenericArray<u8, U20> {
+    let mut out = GenericArray::default();
+    for (chunk, word) in out.chunks_exact_mut(4).zip(h.iter()) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/// Hashes one nonce in each of `N` lanes with a SIMD compression function from `sha1x`, which
+/// the CPU needs to support.
+#[cfg(target_arch = "x86_64")]
+fn finish_simd<const N: usize>(
+    state: &Sha1Midstate,
+    nonces: [&[u8]; N],
+    compress: unsafe fn(&mut [[u32; 5]; N], [&[u8; 64]; N]),
+) -> [GenericArray<u8, U20>; N] {
+    // Each lane's block is the template with that lane's nonce in place
+    let before = &state.template[..state.nonce_offset];
+    let after = &state.template[state.nonce_offset + nonces[0].len()..];
+
+    let mut states = [state.h; N];
+    let mut blocks = [[0; 64]; N];
+    for offset in (0..state.template.len()).step_by(64) {
+        for (block, nonce) in blocks.iter_mut().zip(nonces.iter()) {
+            fill_block(block, offset, &[before, nonce, after]);
+        }
+        unsafe { compress(&mut states, std::array::from_fn(|i| &blocks[i])) };
+    }
+    states.map(to_output)
+}
+
+/// Copies the 64 bytes starting at `offset` out of a message made up of several parts.
+#[cfg(target_arch = "x86_64")]
+fn fill_block(block: &mut [u8; 64], offset: usize, parts: &[&[u8]]) {
+    let mut part_start = 0;
+    for part in parts {
+        let start = offset.max(part_start);
+        let end = (offset + 64).min(part_start + part.len());
+        if start < end {
+            block[start - offset..end - offset]
+                .copy_from_slice(&part[start - part_start..end - part_start]);
+        }
+        part_start += part.len();
+    }
+}
+
+/// Like `Sha1Midstate`, the SHA-256 state after every whole block preceding the nonce, and the
+/// padded rest of the commit.
+#[derive(Clone)]
+pub struct Sha256Midstate {
+    h: [u32; 8],
+    template: Vec<u8>,
+    nonce_offset: usize,
+}
+
+impl PowHasher for Sha256 {
+    const OUTPUT_BITS: u16 = 256;
+    type State = Sha256Midstate;
+    type Output = GenericArray<u8, U32>;
+
+    fn backend() -> String {
+        "scalar".to_string()
+    }
+
+    fn midstate(buf: &[u8], nonce: Range<usize>) -> Self::State {
+        let mut h = crate::sha256::H;
+        let block_start = nonce.start / 64 * 64;
+        for block in buf[..block_start].as_chunks::<64>().0 {
+            crate::sha256::compress(&mut h, block);
+        }
+        Sha256Midstate {
+            h,
+            template: padded_template(buf, block_start),
+            nonce_offset: nonce.start - block_start,
+        }
+    }
+
+    fn finish(state: &mut Self::State, nonce: &[u8]) -> Self::Output {
+        let nonce_offset = state.nonce_offset;
+        state.template[nonce_offset..nonce_offset + nonce.len()].copy_from_slice(nonce);
+
+        let mut h = state.h;
+        for block in state.template.as_chunks::<64>().0 {
+            crate::sha256::compress(&mut h, block);
+        }
+        let mut out = GenericArray::default();
+        for (chunk, word) in out.chunks_exact_mut(4).zip(h.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::from_hex;
+    use crate::{CommitBuffer, NonceLocation};
+    use sha1::Digest;
+
+    /// Hashes `buf` by way of the midstate, with the nonce being whatever `buf` has in `nonce`.
+    fn hash<H: PowHasher>(buf: &[u8], nonce: Range<usize>) -> Vec<u8> {
+        let mut state = H::midstate(buf, nonce.clone());
+        H::finish(&mut state, &buf[nonce]).as_ref().to_vec()
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    #[test]
+    fn sha1_matches_the_reference_digest() {
+        for (len, nonce) in [(40, 10..30), (100, 70..90), (200, 0..32), (300, 268..300)] {
+            let buf = message(len);
+            assert_eq!(hash::<Sha1>(&buf, nonce), Sha1::digest(&buf).to_vec());
+        }
+    }
+
+    #[test]
+    fn padding_fits_around_block_boundaries() {
+        // Messages whose length leaves just enough room for the padding in the last block, or
+        // just too little, which means it spills over into another block of its own
+        for len in [0, 1, 55, 56, 57, 63, 64, 65, 119, 120, 128] {
+            let buf = message(len);
+            let template = padded_template(&buf, 0);
+            assert_eq!(template.len(), (len + 9).div_ceil(64) * 64, "{}", len);
+            assert_eq!(&template[..len], &buf[..]);
+            assert_eq!(template[len], 0x80);
+            assert_eq!(
+                template[template.len() - 8..],
+                (8 * len as u64).to_be_bytes()
+            );
+
+            for nonce in [0..len, len / 2..len, len.saturating_sub(3)..len] {
+                assert_eq!(
+                    hash::<Sha1>(&buf, nonce.clone()),
+                    Sha1::digest(&buf).to_vec(),
+                    "{} {:?}",
+                    len,
+                    nonce
+                );
+                assert_eq!(
+                    hash::<Sha256>(&buf, nonce.clone()),
+                    hash::<Sha256>(&buf, 0..0),
+                    "{} {:?}",
+                    len,
+                    nonce
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_blocks_from_the_nonce_onwards_are_hashed() {
+        let commit = format!(
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A U Thor <author@example.com> 1700000000 +0000\n\
+            committer A U Thor <author@example.com> 1700000000 +0000\n\
+            \n\
+            Long message\n\n{}\n",
+            "All work and no play makes Jack a dull boy. ".repeat(10)
+        );
+        let blocks = |location| {
+            let buf = CommitBuffer::new(commit.as_bytes(), location).unwrap();
+            let state = Sha1::midstate(&buf.buf, buf.nonce_start..buf.nonce_end);
+            assert!(state.nonce_offset < 64);
+            state.template.len() / 64
+        };
+        // The nonce header comes before the message, which is hashed along with it every time
+        assert_eq!(blocks(NonceLocation::Header), 9);
+        // The trailer ends the commit, leaving only the padding after it
+        assert!(blocks(NonceLocation::Trailer) <= 2);
+    }
+
+    #[test]
+    fn every_sha1_backend_agrees() {
+        let buf = message(150);
+        let nonces: Vec<Vec<u8>> = (0..LANES as u8)
+            .map(|lane| (0..20).map(|i| b'a' + (lane + i) % 26).collect())
+            .collect();
+        let nonces = std::array::from_fn(|i| nonces[i].as_slice());
+        // Straddling a block boundary, so that the nonce spans two blocks of the template
+        let mut scalar = Sha1::midstate(&buf, 120..140);
+        scalar.backend = Sha1Backend::Scalar;
+        let expected = nonces.map(|nonce| Sha1::finish(&mut scalar, nonce));
+
+        for backend in Sha1Backend::ALL {
+            if !backend.is_supported() {
+                continue;
+            }
+            let mut state = scalar.clone();
+            state.backend = backend;
+            assert_eq!(
+                Sha1::finish_lanes(&mut state, nonces),
+                expected,
+                "{}",
+                backend
+            );
+            assert_eq!(
+                Sha1::finish(&mut state, nonces[3]),
+                expected[3],
+                "{}",
+                backend
+            );
+        }
+        let mut message = buf.clone();
+        message[120..140].copy_from_slice(nonces[5]);
+        assert_eq!(expected[5].to_vec(), Sha1::digest(&message).to_vec());
+    }
+
+    #[test]
+    fn sha256_matches_known_digests() {
+        let vectors: [(&[u8], &str); 3] = [
+            (
+                b"",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+        ];
+        for (buf, digest) in vectors {
+            let nonce = buf.len() / 2..buf.len();
+            assert_eq!(hash::<Sha256>(buf, nonce), from_hex(digest).unwrap());
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Git hooks running git-power. The first mines every commit as soon as it's made. It's a
+// `post-commit` hook, since the commit needs to exist before it can be mined, and it has
+// git-power rewrite HEAD in place, the same as running it by hand would. git-power writes the
+// mined commit and moves HEAD itself rather than going through `git commit`, so the hook doesn't
+// run again for the mined commit, and a commit that already meets the target, like one amended
+// without changes, is left alone.
+//
+// The other is a `pre-push` hook, which mines nothing, but has `git power verify` check every
+// commit about to be pushed, and rejects the push if any of them falls short.
+
+/// Writes a hook to the repository's hooks directory. The `post-commit` hook mines to `bits`
+/// leading zero bits, or to whatever git-power defaults to at the time when not given, and with
+/// `pre_push`, the `pre-push` hook requires them instead. An existing hook is only replaced
+/// with `force`.
+pub fn install(bits: Option<u16>, pre_push: bool, force: bool) -> Result<()> {
+    let (name, script) = if pre_push {
+        let bits = bits.ok_or_else(|| Error::msg("The pre-push hook needs --bits"))?;
+        ("pre-push", pre_push_script(&executable()?, bits))
+    } else {
+        ("post-commit", post_commit_script(&executable()?, bits))
+    };
+    let path = hooks_dir()?.join(name);
+    if path.exists() && !force {
+        return Err(Error::msg(format!(
+            "{} already exists, pass --force to replace it",
+            path.display()
+        )));
+    }
+    std::fs::write(&path, script)
+        .map_err(|err| Error::msg(format!("Cannot write {}: {}", path.display(), err)))?;
+    make_executable(&path)?;
+    println!("Installed {}", path.display());
+    Ok(())
+}
+
+fn post_commit_script(executable: &str, bits: Option<u16>) -> String {
+    let args = bits.map_or_else(String::new, |bits| format!(" --bits {}", bits));
+    format!(
+        "#!/bin/sh\n\
+         # Installed by `git power hook install`, mines every new commit. git-power moves HEAD\n\
+         # to the mined commit itself, without running this hook again.\n\
+         {exe} verify{args} >/dev/null 2>&1 || exec {exe}{args}\n",
+        exe = executable,
+        args = args
+    )
+}
+
+fn pre_push_script(executable: &str, bits: u16) -> String {
+    // git passes a line for each ref being pushed. A ref being deleted pushes no commits, and
+    // for a ref the remote doesn't have yet, anything not on a remote already is new.
+    format!(
+        r#"#!/bin/sh
+# Installed by `git power hook install --pre-push`, rejects pushing commits with fewer than
+# {bits} leading zero bits.
+zero=$(git hash-object --stdin </dev/null | tr 0-9a-f 0)
+while read -r local_ref local_oid remote_ref remote_oid; do
+    test "$local_oid" = "$zero" && continue
+    if test "$remote_oid" = "$zero"; then
+        range="$local_oid --not --remotes"
+    else
+        range="$remote_oid..$local_oid"
+    fi
+    for commit in $(git rev-list $range); do
+        {exe} verify "$commit" --bits {bits} >/dev/null || exit 1
+    done
+done
+"#,
+        exe = executable,
+        bits = bits
+    )
+}
+
+/// The path to the running binary, quoted for the shell, so that the hook doesn't depend on
+/// the `$PATH` git runs it with.
+fn executable() -> Result<String> {
+    let exe = std::env::current_exe()?;
+    let exe = exe
+        .to_str()
+        .ok_or_else(|| Error::msg(format!("{} is not valid UTF-8", exe.display())))?;
+    Ok(format!("'{}'", exe.replace('\'', r"'\''")))
+}
+
+/// Asks git where the hooks go, which takes `core.hooksPath` and worktrees into account.
+fn hooks_dir() -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::msg(format!(
+            "git rev-parse --git-path hooks failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    std::fs::create_dir_all(&dir)
+        .map_err(|err| Error::msg(format!("Cannot create {}: {}", dir.display(), err)))?;
+    Ok(dir)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).map_err(|err| {
+        Error::msg(format!(
+            "Cannot make {} executable: {}",
+            path.display(),
+            err
+        ))
+    })
+}
+
+/// Elsewhere, git runs hooks without them needing to be marked executable.
+#[cfg(not(unix))]
+fn make_executable(_: &Path) -> Result<()> {
+    Ok(())
+}
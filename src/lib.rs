@@ -0,0 +1,1731 @@
+//! Mining commits whose hashes meet a target, like a number of leading zero bits, by adding a
+//! nonce to them. This is what the `git-power` binary is built on, but it can be used by other
+//! tools as well:
+//!
+//! ```
+//! use git_power::{num_leading_zero_bits, run_pow, CommitBuffer, MineOptions, NoProgress};
+//! use git_power::NonceLocation;
+//! use git_power::{pool::Pool, target::Target, Sha1};
+//!
+//! let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+//!     author A U Thor <author@example.com> 1700000000 +0000\n\
+//!     committer A U Thor <author@example.com> 1700000000 +0000\n\
+//!     \n\
+//!     Initial commit\n";
+//! let buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+//! let target = Target {
+//!     bits: 12,
+//!     ..Target::default()
+//! };
+//! let pool = Pool::new(2, false).unwrap();
+//! let mined = run_pow::<Sha1>(&pool, buf, target, &MineOptions::default(), &NoProgress).unwrap();
+//! assert!(num_leading_zero_bits(&mined.hash) >= 12);
+//! ```
+
+use anyhow::{Error, Result};
+use checkpoint::Checkpoint;
+use hasher::{PowHasher, LANES};
+use log::{debug, info, trace};
+use pool::{CachePadded, Pool};
+use repo::{to_hex, Repo};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::*;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use target::Target;
+
+pub use sha1::Sha1;
+pub use sha256::Sha256;
+
+mod checkpoint;
+pub mod color;
+#[cfg(feature = "cuda")]
+mod cuda;
+#[cfg(test)]
+mod fixtures;
+pub mod future;
+#[cfg(any(feature = "opencl", feature = "cuda"))]
+mod gpu;
+pub mod hasher;
+#[cfg(feature = "opencl")]
+mod opencl;
+pub mod patch;
+pub mod pool;
+pub mod regex;
+pub mod repo;
+mod sha1x;
+pub mod sha256;
+pub mod signal;
+pub mod target;
+
+/// How to go about mining, apart from what the hash needs to look like.
+#[derive(Clone)]
+pub struct MineOptions {
+    /// How long to mine for at most, after which the closest hash found is used instead
+    pub max_time: Option<Duration>,
+    /// Picks where among the nonces to start from, which is the first one without a seed
+    pub seed: Option<u64>,
+    /// File to record how far mining got in every `checkpoint_interval`
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    /// Stops mining once set, for when the commit isn't needed anymore. Mining then fails,
+    /// rather than settling for the closest hash like it does when running out of time.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Default for MineOptions {
+    fn default() -> Self {
+        Self {
+            max_time: None,
+            seed: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(10),
+            cancel: None,
+        }
+    }
+}
+
+impl MineOptions {
+    fn cancelled(&self) -> bool {
+        (self.cancel.as_ref()).is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+const NONCE_LENGTH: usize = 32;
+
+// Number of consecutive nonces a worker thread takes on at a time. Blocks are numbered by a
+// u64, which covers 2^80 nonces, far more than could ever be searched.
+const NONCE_BLOCK_SIZE: u128 = 1 << 16;
+
+/// The block of nonces mining starts from for a given seed. Blocks are taken on from there
+/// onwards, wrapping around, so every nonce is still covered whatever the seed. The seed is
+/// scrambled with SplitMix64, so that similar seeds land far apart.
+fn first_block(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// Number of hashes a worker thread computes before adding them to the shared count, so that
+// the threads don't all contend on it all the time.
+const HASH_COUNT_BATCH: u64 = 4096;
+
+// How often the main thread wakes up while waiting for the workers, even if they have
+// nothing to report.
+const WAKEUP_INTERVAL: Duration = Duration::from_millis(250);
+
+// How often the current hashrate is updated while mining
+const HASHRATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The hash function a repository names its objects by.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ObjectFormat {
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// Number of bits in an object id.
+    pub fn bits(self) -> u16 {
+        match self {
+            Self::Sha1 => Sha1::OUTPUT_BITS,
+            Self::Sha256 => Sha256::OUTPUT_BITS,
+        }
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha1" => Ok(Self::Sha1),
+            "sha256" => Ok(Self::Sha256),
+            _ => Err(Error::msg(format!("Unknown object format: {}", s))),
+        }
+    }
+}
+
+/// Where in a commit the nonce goes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum NonceLocation {
+    /// A `nonce` header following the existing ones, or an armor header of the signature for
+    /// signed commits
+    Header,
+    /// A `Nonce:` trailer at the end of the commit message
+    Trailer,
+}
+
+impl FromStr for NonceLocation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "header" => Ok(Self::Header),
+            "trailer" => Ok(Self::Trailer),
+            _ => Err(Error::msg(format!("Unknown nonce location: {}", s))),
+        }
+    }
+}
+
+/// A commit object with a nonce in it, ready to be hashed with one value of the nonce after
+/// another.
+///
+/// The nonce is always 32 characters from `A` to `P`, each standing for a hex digit of its
+/// value, most significant first, so writing a different value never changes the size of the
+/// object or moves anything else in it. Where it goes is up to the [`NonceLocation`], and an
+/// existing nonce there is taken over rather than another one added. Apart from the nonce, and
+/// the line it's on if it's new, the commit stays exactly as it was given.
+///
+/// ```
+/// use git_power::{CommitBuffer, NonceLocation};
+///
+/// let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+///     author A U Thor <author@example.com> 1700000000 +0000\n\
+///     committer A U Thor <author@example.com> 1700000000 +0000\n\
+///     \n\
+///     Initial commit\n";
+/// let mut buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+/// assert_eq!(buf.nonce(), b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
+/// buf.write_nonce(0x2a);
+/// assert_eq!(buf.nonce(), b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAACK");
+/// assert_eq!(&buf.data()[buf.nonce_range()], buf.nonce());
+/// assert!(buf.data().ends_with(b"nonce AAAAAAAAAAAAAAAAAAAAAAAAAAAAAACK\n\nInitial commit\n"));
+/// ```
+#[derive(Clone)]
+pub struct CommitBuffer {
+    buf: Vec<u8>,
+    header_len: usize,
+    nonce_start: usize,
+    nonce_end: usize,
+}
+
+impl CommitBuffer {
+    /// Prepares the raw contents of a commit for mining, adding a nonce at `location`, or
+    /// taking over the one it already has there. The nonce starts out at zero. This fails if
+    /// the commit lacks a `committer` header, or if the nonce can't go at `location` without
+    /// invalidating the commit's signature.
+    pub fn new(buf: &[u8], location: NonceLocation) -> Result<Self> {
+        let mut buf = buf.to_vec();
+
+        let eol = line_ending(&buf);
+
+        // The nonce will be in different locations depending on whether the commit is signed.
+        //  - If it isn't, then we add the nonce as an additional field in the commit details,
+        //    after all of the existing headers.
+        //  - If the commit is signed, then we add the Nonce as a header *inside* the GPG sig.
+        //    Since the signature is only on the commit contents, the commit will stay signed.
+        //    Any proper GPG client will ignore this header and verify the signature just fine.
+        // `git fsck` insists on `tree`, `parent`, `author` and `committer` coming first, in that
+        // order, so we only ever look at (and add) headers following the `committer` line.
+        let headers = parse_headers(&buf, eol);
+        let committer = headers
+            .iter()
+            .position(|header| buf[header.start..header.end].starts_with(b"committer "))
+            .ok_or("Malformed commit")
+            .map_err(Error::msg)?;
+        let find_header = |key: &[u8]| {
+            headers[committer + 1..]
+                .iter()
+                .find(|header| buf[header.start..header.end].starts_with(key))
+                .cloned()
+        };
+        // SHA-256 repositories store the signature under `gpgsig-sha256` instead
+        let signature = find_header(b"gpgsig ").or_else(|| find_header(b"gpgsig-sha256 "));
+        let start = match (location, signature) {
+            (NonceLocation::Trailer, Some(_)) => {
+                return Err(Error::msg(
+                    "Cannot add a nonce trailer to a signed commit without invalidating its signature",
+                ));
+            }
+            (NonceLocation::Trailer, None) => {
+                // The trailer goes at the very end of the message. If the message already ends
+                // in a block of trailers, it joins that block, otherwise it gets its own
+                // paragraph, like `git interpret-trailers` would do.
+                let header_end = headers[headers.len() - 1].end;
+                let body_start = (header_end + 2 * eol.len()).min(buf.len());
+                let mut msg_end = buf.len();
+                while msg_end > body_start && buf[body_start..msg_end].ends_with(eol) {
+                    msg_end -= eol.len();
+                }
+                let message = &buf[body_start..msg_end];
+                let paragraph_start = rfind(message, &[eol, eol].concat());
+                let paragraph =
+                    paragraph_start.map_or(message, |idx| &message[idx + 2 * eol.len()..]);
+                let last_line =
+                    rfind(paragraph, eol).map_or(paragraph, |idx| &paragraph[idx + eol.len()..]);
+                if !message.is_empty() && last_line.starts_with(b"Nonce: ") {
+                    msg_end - last_line.len() + b"Nonce: ".len()
+                } else {
+                    // The subject line never counts as a trailer
+                    let in_trailers = paragraph_start.is_some()
+                        && paragraph
+                            .split(|&c| c == b'\n')
+                            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+                            .all(|line| key_value(line).is_some());
+                    let mut trailer = Vec::new();
+                    if message.is_empty() {
+                        buf.truncate(header_end);
+                        trailer.extend_from_slice(eol);
+                        trailer.extend_from_slice(eol);
+                    } else {
+                        buf.truncate(msg_end);
+                        trailer.extend_from_slice(eol);
+                        if !in_trailers {
+                            trailer.extend_from_slice(eol);
+                        }
+                    }
+                    trailer.extend_from_slice(b"Nonce: ");
+                    let nonce_offset = buf.len() + trailer.len();
+                    trailer.extend_from_slice(eol);
+                    buf.extend_from_slice(&trailer);
+                    nonce_offset
+                }
+            }
+            (NonceLocation::Header, Some(sig)) => {
+                let armor = parse_armor_headers(&buf[sig.clone()], eol)?;
+                let nonce_header = [eol, b" Nonce: "].concat();
+                match armor.nonce {
+                    Some(idx) => sig.start + idx,
+                    None => {
+                        let armor_end = sig.start + armor.end;
+                        insert_bytes(&mut buf, armor_end, &nonce_header);
+                        // A signature lacking the blank line before its payload gets one, since
+                        // our header would otherwise be read as part of the base64 data.
+                        if !armor.terminated {
+                            let blank_line = [eol, b" "].concat();
+                            insert_bytes(&mut buf, armor_end + nonce_header.len(), &blank_line);
+                        }
+                        armor_end + nonce_header.len()
+                    }
+                }
+            }
+            (NonceLocation::Header, None) => match find_header(b"nonce ") {
+                // Only an actual header line counts as an existing nonce, so that any mention
+                // of the word in the commit message itself doesn't get clobbered.
+                Some(nonce) => nonce.start + 6,
+                None => {
+                    // Otherwise the nonce goes in as the very last header, so that optional
+                    // headers like `encoding` keep their position after `committer` and the
+                    // object still decodes the same way. Existing headers aren't touched.
+                    // Since we go by the headers rather than the message, this also works for
+                    // commits with an empty message, where the object ends after the headers.
+                    let header_end = headers[headers.len() - 1].end;
+                    let nonce_header = [eol, b"nonce "].concat();
+                    insert_bytes(&mut buf, header_end, &nonce_header);
+                    header_end + nonce_header.len()
+                }
+            },
+        };
+
+        // Here we normalize the nonce to its initial value. If the commit already carries a
+        // nonce (e.g. it was mined before), it may be of a different length, so we replace
+        // the whole value and only then take the nonce's span from the edited buffer.
+        // Objects lacking a trailing newline can end right after the nonce.
+        let line_end = start + find(&buf[start..], eol).unwrap_or(buf.len() - start);
+        buf.splice(start..line_end, [b'A'; NONCE_LENGTH].iter().cloned());
+        let end = start + NONCE_LENGTH;
+
+        // When git hashes an object, it prepends a header to the data which inclues the type
+        // of the object, as well as its size. We opt to call out to a faster SHA-1 library,
+        // so we need to prepend the header ourselves. This has to happen last, since the
+        // size is that of the final buffer.
+        let commit_header = format!("commit {}\0", buf.len());
+        insert_bytes(&mut buf, 0, commit_header.as_bytes());
+        let header_len = commit_header.len();
+        Ok(Self {
+            buf,
+            header_len,
+            nonce_start: start + header_len,
+            nonce_end: end + header_len,
+        })
+    }
+
+    /// Writes the nonce with the value `val`.
+    pub fn write_nonce(&mut self, val: u128) {
+        self.buf[self.nonce_start..self.nonce_end].copy_from_slice(&encode_nonce(val));
+    }
+
+    /// A copy of the commit with the given nonce written to it.
+    pub fn with_nonce(&self, val: u128) -> Self {
+        let mut commit = self.clone();
+        commit.write_nonce(val);
+        commit
+    }
+
+    /// Prepares for hashing the commit with different nonces.
+    fn midstate<H: PowHasher>(&self) -> H::State {
+        H::midstate(&self.buf, self.nonce_start..self.nonce_end)
+    }
+
+    /// The nonce as it's currently written.
+    pub fn nonce(&self) -> &[u8] {
+        &self.buf[self.nonce_start..self.nonce_end]
+    }
+
+    /// Where the nonce is within [`data`](Self::data), which is the same whatever its value.
+    pub fn nonce_range(&self) -> Range<usize> {
+        self.nonce_start - self.header_len..self.nonce_end - self.header_len
+    }
+
+    /// The contents of the commit object, as they'd be written to the repository. The id of
+    /// the commit is the hash of these, after the `commit <size>\0` header git prepends to
+    /// every object.
+    pub fn data(&self) -> &[u8] {
+        // We just want the commit data itself, minus the prepended metadata header
+        &self.buf[self.header_len..]
+    }
+}
+
+impl fmt::Display for CommitBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.data()))
+    }
+}
+
+/// Objects that passed through Windows tooling may use CRLF line endings, so we pick up the
+/// convention from the first line and stick to it for everything we insert.
+fn line_ending(buf: &[u8]) -> &'static [u8] {
+    match find(buf, b"\n") {
+        Some(idx) if idx > 0 && buf[idx - 1] == b'\r' => b"\r\n",
+        _ => b"\n",
+    }
+}
+
+/// Points the parents of a commit at their rewritten versions, as given by `rewritten`,
+/// leaving everything else about the commit as it is.
+fn replace_parents(commit: &[u8], rewritten: &HashMap<Vec<u8>, Vec<u8>>) -> Result<Vec<u8>> {
+    let eol = line_ending(commit);
+    let mut buf = commit.to_vec();
+    // Going backwards keeps the ranges of the headers yet to be replaced intact
+    for header in parse_headers(commit, eol).into_iter().rev() {
+        let line = &commit[header.clone()];
+        if let Some(parent) = line.strip_prefix(b"parent ") {
+            let parent = repo::from_hex(&String::from_utf8_lossy(parent))?;
+            if let Some(new_parent) = rewritten.get(&parent) {
+                let value = to_hex(new_parent);
+                buf.splice(header.start + 7..header.end, value.bytes());
+            }
+        }
+    }
+    Ok(buf)
+}
+
+/// Whether a commit carries a signature, which covers its parents as well.
+pub fn is_signed(commit: &[u8]) -> bool {
+    parse_headers(commit, line_ending(commit))
+        .into_iter()
+        .any(|header| commit[header].starts_with(b"gpgsig"))
+}
+
+/// Writes the commits in `descendants`, given as ids along with their contents, again on top
+/// of `new` in place of `old`, so that they keep following it. Returns the id the last of them
+/// ends up with.
+pub fn rewrite_descendants(
+    repo: &dyn Repo,
+    descendants: &[(Vec<u8>, Vec<u8>)],
+    old: &[u8],
+    new: &[u8],
+) -> Result<Vec<u8>> {
+    let mut rewritten = HashMap::new();
+    rewritten.insert(old.to_vec(), new.to_vec());
+    let mut head = new.to_vec();
+    for (id, commit) in descendants {
+        head = repo.write_commit(&replace_parents(commit, &rewritten)?)?;
+        rewritten.insert(id.clone(), head.clone());
+    }
+    Ok(head)
+}
+
+fn encode_nonce(val: u128) -> [u8; NONCE_LENGTH] {
+    // Only covers 128/160 bits of entropy. Possible chars: ABCDEFGHIJKLMNOP
+    let mut nonce = [0; NONCE_LENGTH];
+    for (i, byte) in nonce.iter_mut().rev().enumerate() {
+        *byte = b'A' + ((val >> (4 * i)) & 0xF) as u8;
+    }
+    nonce
+}
+
+/// A nonce that is moved on to the next value in place, rather than being encoded from scratch
+/// for every attempt. It always holds `encode_nonce` of its current value, and since the least
+/// significant digit comes last, incrementing it usually only touches the last byte.
+#[derive(Clone, Copy)]
+struct NonceCounter([u8; NONCE_LENGTH]);
+
+impl NonceCounter {
+    fn new(val: u128) -> Self {
+        Self(encode_nonce(val))
+    }
+
+    /// Returns the current nonce and moves on to the next one, wrapping around after the last.
+    fn next(&mut self) -> [u8; NONCE_LENGTH] {
+        let nonce = self.0;
+        for byte in self.0.iter_mut().rev() {
+            if *byte == b'P' {
+                *byte = b'A';
+            } else {
+                *byte += 1;
+                break;
+            }
+        }
+        nonce
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+fn insert_bytes(buf: &mut Vec<u8>, idx: usize, bytes: &[u8]) {
+    buf.splice(idx..idx, bytes.iter().cloned());
+}
+
+/// Walks the header block of a commit object, returning the span of each header line
+/// without its line terminator. Continuation lines (those beginning with a space) are
+/// folded into the span of the header they belong to, and the blank line separating the
+/// headers from the message ends the block. This matters for multi-line headers such as
+/// `mergetag`, which embeds a whole tag object (blank line and signature included) as
+/// continuation lines.
+fn parse_headers(buf: &[u8], eol: &[u8]) -> Vec<Range<usize>> {
+    let mut headers: Vec<Range<usize>> = Vec::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let line_end = pos + find(&buf[pos..], eol).unwrap_or(buf.len() - pos);
+        if line_end == pos {
+            break;
+        }
+        match headers.last_mut() {
+            Some(header) if buf[pos] == b' ' => header.end = line_end,
+            _ => headers.push(pos..line_end),
+        }
+        pos = line_end + eol.len();
+    }
+    headers
+}
+
+/// Splits a `Key: Value` line, as found in both armor headers and commit message trailers.
+fn key_value(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let idx = find(line, b": ")?;
+    let key = &line[..idx];
+    if key.is_empty() || !key.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'-') {
+        return None;
+    }
+    Some((key, &line[idx + 2..]))
+}
+
+struct ArmorHeaders {
+    /// Offset of the value of an existing `Nonce` armor header, if there is one.
+    nonce: Option<usize>,
+    /// Offset of the line terminator ending the last line of the armor header block.
+    end: usize,
+    /// Whether the block is followed by the blank line separating it from the payload.
+    terminated: bool,
+}
+
+/// Parses the armor header block of the PGP signature held in a `gpgsig` header. Every line
+/// of the armor after the first is a continuation line of the commit header, so the leading
+/// space is dropped before looking at it. The block consists of `Key: Value` lines following
+/// the `-----BEGIN PGP SIGNATURE-----` line, and ends at the first line that is either blank
+/// or doesn't look like an armor header, which is where the base64 payload begins.
+///
+/// Git can also sign with SSH keys or x509 certificates, but neither kind of signature has
+/// armor headers, so there's nowhere in it for a nonce to go.
+fn parse_armor_headers(sig: &[u8], eol: &[u8]) -> Result<ArmorHeaders> {
+    for (armor, kind) in [
+        (&b"-----BEGIN SSH SIGNATURE-----"[..], "an SSH"),
+        (b"-----BEGIN SIGNED MESSAGE-----", "an x509"),
+    ] {
+        if find(sig, armor).is_some() {
+            return Err(Error::msg(format!(
+                "Cannot add a nonce to {} signature, since only PGP signatures have armor \
+                headers to carry one. Remove the signature and mine with \
+                --nonce-location trailer, or sign the commit with PGP instead",
+                kind
+            )));
+        }
+    }
+    let begin = find(sig, b"-----BEGIN PGP SIGNATURE-----")
+        .ok_or("Malformed PGP header")
+        .map_err(Error::msg)?;
+    let mut end = begin + find(&sig[begin..], eol).unwrap_or(sig.len() - begin);
+    let mut nonce = None;
+    while end < sig.len() {
+        let line_start = end + eol.len();
+        let line_end = line_start + find(&sig[line_start..], eol).unwrap_or(sig.len() - line_start);
+        let line = sig[line_start..line_end]
+            .strip_prefix(b" ")
+            .ok_or("Malformed PGP header")
+            .map_err(Error::msg)?;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(ArmorHeaders {
+                nonce,
+                end,
+                terminated: true,
+            });
+        }
+        match key_value(line) {
+            Some((b"Nonce", _)) => nonce = Some(line_start + 1 + b"Nonce: ".len()),
+            Some(_) => {}
+            None => break,
+        }
+        end = line_end;
+    }
+    Ok(ArmorHeaders {
+        nonce,
+        end,
+        terminated: false,
+    })
+}
+
+/// A commit with a nonce meeting the target, or at least coming closest to it.
+pub struct Mined {
+    pub commit: CommitBuffer,
+    /// The id the commit gets
+    pub hash: Vec<u8>,
+    /// Number of nonces tried
+    pub attempts: u128,
+    pub elapsed: Duration,
+}
+
+impl Mined {
+    /// The statistics of the search that came up with this commit.
+    pub fn stats(&self, outcome: Outcome) -> Stats<'_> {
+        Stats {
+            outcome,
+            attempts: self.attempts,
+            elapsed: self.elapsed,
+            hash: Some(&self.hash),
+        }
+    }
+}
+
+/// How a search came to an end.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    /// A hash meeting the target was found
+    Found,
+    OutOfTime,
+    Interrupted,
+    /// Every nonce was tried without meeting the target
+    Exhausted,
+}
+
+/// What there is to tell about a search once it's over.
+pub struct Stats<'a> {
+    pub outcome: Outcome,
+    /// Number of nonces tried
+    pub attempts: u128,
+    pub elapsed: Duration,
+    /// The hash the commit ends up with, which is the closest one found if the search was cut
+    /// short. Without a time limit, or if nothing came close, there is none.
+    pub hash: Option<&'a [u8]>,
+}
+
+/// Receives the progress of a search as it happens, to show it however suits. All of it is
+/// called from the thread mining was started on.
+pub trait ProgressSink {
+    /// A hash closer to the target than any before it was found, with `zeros` leading zero bits.
+    fn on_improvement(&self, hash: &[u8], zeros: u16);
+
+    /// The search is over, whether or not it succeeded. Errors other than the search coming up
+    /// empty handed end it without this being called.
+    fn on_done(&self, stats: &Stats);
+
+    /// The number of hashes being computed, in MH/s, which is sampled every so often.
+    fn on_hashrate(&self, _mhps: f64) {}
+}
+
+/// A [`ProgressSink`] that ignores everything.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn on_improvement(&self, _: &[u8], _: u16) {}
+    fn on_done(&self, _: &Stats) {}
+}
+
+// Only the nonce is sent back, so that the threads don't need the commit buffer at all
+enum PowMessage {
+    Update(u128, Vec<u8>, u16),
+    Done(u128, Vec<u8>),
+    Exhausted,
+}
+
+/// A nonce along with the hash the commit gets with it.
+type Candidate = (u128, Vec<u8>);
+
+pub fn num_leading_zero_bits(hash: &[u8]) -> u16 {
+    let mut zeros = 0;
+    for &byte in hash.iter() {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros as u16
+}
+
+/// Whether any of the hashes could have more than `min_zeros` leading zero bits, judging from
+/// their first four bytes. Every lane is compared at once, which the compiler turns into a few
+/// SIMD instructions, so that most groups of hashes are ruled out without looking at each one.
+fn any_leading_zeros_above<T: AsRef<[u8]>>(hashes: &[T; LANES], min_zeros: u16) -> bool {
+    // More than `min_zeros` leading zero bits puts the first word below 2^(31 - min_zeros)
+    let bound = 1u32 << (31 - min_zeros.min(31));
+    let tops: [u32; LANES] = std::array::from_fn(|i| {
+        let hash = hashes[i].as_ref();
+        u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]])
+    });
+    tops.iter().fold(false, |any, &top| any | (top < bound))
+}
+
+fn num_trailing_zero_bits(hash: &[u8]) -> u16 {
+    let mut zeros = 0;
+    for &byte in hash.iter().rev() {
+        zeros += byte.trailing_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros as u16
+}
+
+/// Hashes the commit with the next `LANES` nonces of `counter`. Since the part of the commit
+/// before the nonce never changes, hashing starts from the state computed up to the nonce's
+/// location.
+fn hash_lanes<H: PowHasher>(
+    midstate: &mut H::State,
+    counter: &mut NonceCounter,
+) -> [H::Output; LANES] {
+    let nonces: [_; LANES] = std::array::from_fn(|_| counter.next());
+    H::finish_lanes(midstate, std::array::from_fn(|i| &nonces[i][..]))
+}
+
+/// Hashes the commit with every nonce it can get through in the given amount of time, without
+/// checking for any target, and returns how many hashes each thread computed. If Ctrl-C is
+/// pressed in the meantime, the threads stop early, and since the counts would be off, this
+/// fails instead.
+pub fn measure_hashrate<H: PowHasher>(
+    commit: &CommitBuffer,
+    num_threads: usize,
+    duration: Duration,
+) -> Result<Vec<u64>> {
+    let deadline = Instant::now() + duration;
+    let counts = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..num_threads)
+            .map(|i| {
+                scope.spawn(move || {
+                    let mut midstate = commit.midstate::<H>();
+                    let start = i as u128 * (u128::MAX / num_threads as u128);
+                    let mut counter = NonceCounter::new(start);
+                    let mut count = 0;
+                    // Checking the time is comparatively expensive, so it's only done once
+                    // in a while
+                    while count % 1024 != 0 || (Instant::now() < deadline && !signal::interrupted())
+                    {
+                        for hash in hash_lanes::<H>(&mut midstate, &mut counter) {
+                            std::hint::black_box(num_leading_zero_bits(hash.as_ref()));
+                        }
+                        count += LANES as u64;
+                    }
+                    count
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    if signal::interrupted() {
+        return Err(Error::msg("Interrupted"));
+    }
+    Ok(counts)
+}
+
+/// Converts a number of hashes done in the given time to MH/s. Should no time have passed at
+/// all, which a run meeting an easy target can come close to, this gives zero instead of an
+/// infinite rate.
+pub fn to_mhps(num_hashes: f64, elapsed: Duration) -> f64 {
+    let num_seconds = elapsed.as_secs_f64();
+    if num_seconds > 0.0 {
+        num_hashes / 1_000_000.0 / num_seconds
+    } else {
+        0.0
+    }
+}
+
+pub fn format_seconds(seconds: f64) -> String {
+    const UNITS: &[(&str, f64)] = &[
+        ("years", 365.0 * 24.0 * 3600.0),
+        ("days", 24.0 * 3600.0),
+        ("hours", 3600.0),
+        ("minutes", 60.0),
+    ];
+    for &(unit, length) in UNITS {
+        if seconds >= length {
+            return format!("{:.1} {}", seconds / length, unit);
+        }
+    }
+    format!("{:.3} seconds", seconds)
+}
+
+/// Mines the commit on the threads of `pool`, which can be reused for mining several commits
+/// in a row, telling `sink` how it's going.
+pub fn run_pow<H: PowHasher + 'static>(
+    pool: &Pool,
+    commit: CommitBuffer,
+    target: Target,
+    options: &MineOptions,
+    sink: &dyn ProgressSink,
+) -> Result<Mined> {
+    search::<H>(pool, commit, target, options, sink, u64::MAX)
+}
+
+/// Does the mining for [`run_pow`], in the first `num_blocks` blocks of nonces rather than all
+/// of them, so that tests can get to the end of them.
+fn search<H: PowHasher + 'static>(
+    pool: &Pool,
+    commit: CommitBuffer,
+    target: Target,
+    options: &MineOptions,
+    sink: &dyn ProgressSink,
+    num_blocks: u64,
+) -> Result<Mined> {
+    let start_time = Instant::now();
+    let (tx, rx) = mpsc::channel();
+    // `stop` is read for every attempt, while the others are written to every so often, so
+    // each of them is kept on a cache line of its own. Everything a thread writes to for each
+    // attempt is its own copy of the midstate, which it allocates itself.
+    let stop = Arc::new(CachePadded(AtomicBool::new(false)));
+    let num_hashes = Arc::new(CachePadded(AtomicU64::new(0)));
+    let max_score = Arc::new(CachePadded(AtomicU16::new(0)));
+    let next_block = Arc::new(CachePadded(AtomicU64::new(0)));
+    let first_block = options.seed.map_or(0, first_block);
+    // With nothing but leading zeros to look for, a hash is only of interest if it has more
+    // of them than the best one so far. Asking for none at all, every hash is of interest.
+    let zeros_only = target.bits > 0
+        && Target {
+            bits: 0,
+            ..target.clone()
+        }
+        .is_trivial();
+    let target = Arc::new(target);
+
+    // Each thread repeatedly takes the next block of nonces from the shared cursor
+    // `next_block`, loops through each nonce in it and calculates a hash, and if it satisfies
+    // the POW requirement, sends it to the main thread. When this happens, we stop all other
+    // threads using the AtomicBool `stop`. Since blocks are handed out on demand, no thread
+    // runs out of work before the others do.
+    let num_threads = pool.num_threads();
+    info!("Mining with {} threads using {}", num_threads, H::backend());
+    let midstate = commit.midstate::<H>();
+    let batch = {
+        let stop = Arc::clone(&stop);
+        let num_hashes = Arc::clone(&num_hashes);
+        let max_score = Arc::clone(&max_score);
+        let next_block = Arc::clone(&next_block);
+        let target = Arc::clone(&target);
+        pool.broadcast(move |thread| {
+            let mut midstate = midstate.clone();
+            let mut uncounted = 0;
+            // Once every block was handed out, taking another one fails
+            while let Ok(block) =
+                next_block.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |block| {
+                    (block < num_blocks).then(|| block + 1)
+                })
+            {
+                let start = first_block.wrapping_add(block) as u128 * NONCE_BLOCK_SIZE;
+                debug!(
+                    "Thread {} takes on nonces {}..{}",
+                    thread,
+                    start,
+                    start + NONCE_BLOCK_SIZE
+                );
+                let mut counter = NonceCounter::new(start);
+                for base in (start..start + NONCE_BLOCK_SIZE).step_by(LANES) {
+                    // Check if a hash was already found by another thread
+                    if stop.load(Ordering::Relaxed) {
+                        num_hashes.fetch_add(uncounted, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    uncounted += LANES as u64;
+                    if uncounted >= HASH_COUNT_BATCH {
+                        num_hashes.fetch_add(uncounted, Ordering::Relaxed);
+                        uncounted = 0;
+                    }
+
+                    // Hash consecutive nonces together
+                    let hashes = hash_lanes::<H>(&mut midstate, &mut counter);
+                    if zeros_only
+                        && !any_leading_zeros_above(&hashes, max_score.load(Ordering::Relaxed))
+                    {
+                        continue;
+                    }
+                    for (lane, hash) in hashes.iter().enumerate() {
+                        let hash = hash.as_ref();
+                        let nonce = base.wrapping_add(lane as u128);
+
+                        // Check against our win condition
+                        let num_zeros = num_leading_zero_bits(hash);
+                        let score = target.score(hash, num_zeros);
+                        // The plain load filters out the common case without contending on the
+                        // cache line, and `fetch_max` then makes sure only one thread reports a
+                        // given record.
+                        if score > max_score.load(Ordering::Relaxed)
+                            && score > max_score.fetch_max(score, Ordering::Relaxed)
+                        {
+                            trace!(
+                                "Thread {} found {} with a score of {}",
+                                thread,
+                                to_hex(hash),
+                                score
+                            );
+                            tx.send(PowMessage::Update(nonce, hash.to_vec(), score))?;
+                        }
+                        if target.matches(hash, num_zeros) {
+                            num_hashes.fetch_add(uncounted, Ordering::Relaxed);
+                            tx.send(PowMessage::Done(nonce, hash.to_vec()))?;
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            // We made it through every block without a hit
+            num_hashes.fetch_add(uncounted, Ordering::Relaxed);
+            tx.send(PowMessage::Exhausted)?;
+            Ok(())
+        })
+    };
+
+    let deadline = options.max_time.map(|max_time| start_time + max_time);
+
+    // Waits for the search to end, which is how it ended and the nonce and hash to go with it,
+    // if there are any to go with
+    let receive = || -> Result<(Outcome, Option<Candidate>)> {
+        let mut num_exhausted = 0;
+        let mut best_score = 0;
+        let mut best = None;
+        let mut sampled = (start_time, 0);
+        let mut checkpointed = start_time;
+
+        loop {
+            // Rather than waiting for the workers indefinitely, we wake up every so often to
+            // check on the time and the hashrate
+            let now = Instant::now();
+            if now.duration_since(sampled.0) >= HASHRATE_INTERVAL {
+                let num_hashes = num_hashes.load(Ordering::Relaxed);
+                let elapsed = now.duration_since(sampled.0);
+                sink.on_hashrate(to_mhps((num_hashes - sampled.1) as f64, elapsed));
+                sampled = (now, num_hashes);
+            }
+            if let Some(path) = &options.checkpoint {
+                if now.duration_since(checkpointed) >= options.checkpoint_interval {
+                    Checkpoint {
+                        seed: options.seed,
+                        position: next_block.load(Ordering::Relaxed) as u128 * NONCE_BLOCK_SIZE,
+                        attempts: num_hashes.load(Ordering::Relaxed) as u128,
+                        elapsed: now.duration_since(start_time),
+                        best: best
+                            .as_ref()
+                            .map(|(nonce, hash): &Candidate| (*nonce, hash.as_slice())),
+                    }
+                    .write(path)?;
+                    checkpointed = now;
+                }
+            }
+            if options.cancelled() {
+                stop.store(true, Ordering::Relaxed);
+                return Err(Error::msg("Mining was cancelled"));
+            }
+            let out_of_time = deadline.is_some_and(|deadline| now >= deadline);
+            if out_of_time {
+                return Ok((Outcome::OutOfTime, best));
+            }
+            if signal::interrupted() {
+                // Being interrupted is only as good as running out of time if a near miss
+                // was asked for, by giving a time limit
+                return Ok((Outcome::Interrupted, best.filter(|_| deadline.is_some())));
+            }
+            let timeout = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(now).min(WAKEUP_INTERVAL),
+                None => WAKEUP_INTERVAL,
+            };
+            let message = match rx.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => continue,
+                // This only happens once every worker has hung up, which means they died
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::msg("Worker threads exited unexpectedly"))
+                }
+            };
+            match message {
+                // Updates from different threads can still arrive out of order
+                PowMessage::Update(_, _, score) if score <= best_score => {}
+                PowMessage::Update(nonce, hash, score) => {
+                    best_score = score;
+                    let num_zeros = num_leading_zero_bits(&hash);
+                    sink.on_improvement(&hash, num_zeros);
+                    // A hash meeting the target can be reported before another thread's
+                    // solution arrives, in which case it has to be the one that's used, since
+                    // it's the one that was shown
+                    if target.matches(&hash, num_zeros) {
+                        return Ok((Outcome::Found, Some((nonce, hash))));
+                    }
+                    best = Some((nonce, hash));
+                }
+                PowMessage::Done(nonce, hash) => {
+                    // Had its update come through, we'd have returned on it already. It never
+                    // got sent if another thread got to the score first, so the hash is only
+                    // shown now.
+                    sink.on_improvement(&hash, num_leading_zero_bits(&hash));
+                    return Ok((Outcome::Found, Some((nonce, hash))));
+                }
+                PowMessage::Exhausted => {
+                    num_exhausted += 1;
+                    if num_exhausted == num_threads {
+                        return Ok((Outcome::Exhausted, None));
+                    }
+                }
+            }
+        }
+    };
+    let ended = receive();
+
+    // Whatever the outcome, wind down the remaining workers and wait for them to finish,
+    // since an error in any of them takes precedence over the result.
+    stop.store(true, Ordering::Relaxed);
+    batch.wait()?;
+    let (outcome, found) = ended?;
+    // Only now have the workers counted every hash they tried
+    let attempts = num_hashes.load(Ordering::Relaxed) as u128;
+    let elapsed = start_time.elapsed();
+    sink.on_done(&Stats {
+        outcome,
+        attempts,
+        elapsed,
+        hash: found.as_ref().map(|(_, hash)| hash.as_slice()),
+    });
+    match (found, outcome) {
+        // When out of time or interrupted, this settles for the closest we got to the target
+        (Some((nonce, hash)), _) => Ok(Mined {
+            commit: commit.with_nonce(nonce),
+            hash,
+            attempts,
+            elapsed,
+        }),
+        (None, Outcome::Exhausted) => Err(Error::msg(format!(
+            "No solution found for {} in the searched space of {} nonces",
+            target,
+            num_blocks as u128 * NONCE_BLOCK_SIZE
+        ))),
+        (None, Outcome::Interrupted) if deadline.is_none() => {
+            Err(Error::msg("Interrupted, so the commit was left as it was"))
+        }
+        (None, _) => Err(Error::msg(format!("No hash found for {} in time", target))),
+    }
+}
+
+/// What mining turns up, as yielded by [`run_pow_iter`].
+pub enum PowEvent {
+    /// A hash closer to the target than any before it, with its number of leading zero bits
+    Improvement(Vec<u8>, u16),
+    /// The commit mining ended with, which is always the last thing yielded
+    Solution(Mined),
+}
+
+/// Starts mining the commit on the threads of `pool`, giving back an iterator over each hash
+/// coming closer to the target until the solution. Mining goes on in the background whether or
+/// not the iterator is consumed, with the hashes waiting to be taken from it. If mining fails,
+/// the error takes the place of the solution. Dropping the iterator cancels mining, by way of
+/// `options.cancel`, which is replaced with a flag of the iterator's own.
+pub fn run_pow_iter<H: PowHasher + 'static>(
+    pool: Pool,
+    commit: CommitBuffer,
+    target: Target,
+    mut options: MineOptions,
+) -> PowEvents {
+    struct Events(mpsc::Sender<Result<PowEvent>>);
+
+    impl ProgressSink for Events {
+        fn on_improvement(&self, hash: &[u8], zeros: u16) {
+            // Nobody is listening anymore if the iterator was dropped
+            let _ = self.0.send(Ok(PowEvent::Improvement(hash.to_vec(), zeros)));
+        }
+
+        fn on_done(&self, _: &Stats) {}
+    }
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    options.cancel = Some(Arc::clone(&cancel));
+    let (tx, rx) = mpsc::channel();
+    let thread = std::thread::spawn(move || {
+        let events = Events(tx);
+        let result = run_pow::<H>(&pool, commit, target, &options, &events);
+        let _ = events.0.send(result.map(PowEvent::Solution));
+    });
+    PowEvents {
+        events: rx,
+        cancel,
+        thread: Some(thread),
+    }
+}
+
+/// The iterator returned by [`run_pow_iter`].
+pub struct PowEvents {
+    events: mpsc::Receiver<Result<PowEvent>>,
+    cancel: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Iterator for PowEvents {
+    type Item = Result<PowEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.recv().ok()
+    }
+}
+
+impl Drop for PowEvents {
+    // Waiting for the mining thread makes sure the workers are done by the time this returns,
+    // which takes until the main thread next wakes up at most
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(any(feature = "opencl", feature = "cuda"))]
+pub fn run_pow_gpu(
+    commit: CommitBuffer,
+    target: Target,
+    options: &MineOptions,
+    sink: &dyn ProgressSink,
+) -> Result<Mined> {
+    // Number of nonces searched by a single run of the kernel. Between runs, we check the
+    // time and print the progress.
+    const GPU_BATCH_SIZE: usize = 1 << 24;
+
+    // The kernel only counts leading zeros, so anything else would have to be checked on
+    // the CPU, which would defeat the purpose
+    let bits_only = Target {
+        bits: 0,
+        ..target.clone()
+    };
+    if !bits_only.is_trivial() {
+        return Err(Error::msg(
+            "Mining on a GPU only supports targets of leading zeros",
+        ));
+    }
+
+    let start_time = Instant::now();
+    let deadline = options.max_time.map(|max_time| start_time + max_time);
+    let midstate = commit.midstate::<Sha1>();
+    let mut gpu = gpu::open(&midstate, target.bits)?;
+    let mut checkpointed = start_time;
+    info!("Mining on {}", gpu.name());
+    let start = options.seed.map_or(0, first_block) as u128 * NONCE_BLOCK_SIZE;
+    for searched in (0..u128::MAX).step_by(GPU_BATCH_SIZE) {
+        let base = start.wrapping_add(searched);
+        if let Some(nonce) = gpu.search(base, GPU_BATCH_SIZE)? {
+            // Double-check the hash on the CPU before trusting it
+            let hash = Sha1::finish(&mut midstate.clone(), &encode_nonce(nonce));
+            if !target.matches(&hash, num_leading_zero_bits(&hash)) {
+                return Err(Error::msg(format!(
+                    "The GPU came up with {}, which doesn't meet the target of {}",
+                    to_hex(&hash),
+                    target
+                )));
+            }
+            let mined = Mined {
+                commit: commit.with_nonce(nonce),
+                hash: hash.to_vec(),
+                attempts: searched + GPU_BATCH_SIZE as u128,
+                elapsed: start_time.elapsed(),
+            };
+            sink.on_improvement(&mined.hash, num_leading_zero_bits(&mined.hash));
+            sink.on_done(&mined.stats(Outcome::Found));
+            return Ok(mined);
+        }
+        let num_hashes = searched + GPU_BATCH_SIZE as u128;
+        let stopped = |outcome| Stats {
+            outcome,
+            attempts: num_hashes,
+            elapsed: start_time.elapsed(),
+            hash: None,
+        };
+        if options.cancelled() {
+            return Err(Error::msg("Mining was cancelled"));
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            sink.on_done(&stopped(Outcome::OutOfTime));
+            return Err(Error::msg(format!("No hash found for {} in time", target)));
+        }
+        if signal::interrupted() {
+            sink.on_done(&stopped(Outcome::Interrupted));
+            return Err(Error::msg("Interrupted, so the commit was left as it was"));
+        }
+        sink.on_hashrate(to_mhps(num_hashes as f64, start_time.elapsed()));
+        if let Some(path) = &options.checkpoint {
+            if checkpointed.elapsed() >= options.checkpoint_interval {
+                // Only hashes meeting the target come back from the GPU, so there's no best one
+                Checkpoint {
+                    seed: options.seed,
+                    position: num_hashes,
+                    attempts: num_hashes,
+                    elapsed: start_time.elapsed(),
+                    best: None,
+                }
+                .write(path)?;
+                checkpointed = Instant::now();
+            }
+        }
+    }
+    sink.on_done(&Stats {
+        outcome: Outcome::Exhausted,
+        attempts: u128::MAX,
+        elapsed: start_time.elapsed(),
+        hash: None,
+    });
+    Err(Error::msg(format!(
+        "No solution found for {} in the searched space of {} nonces",
+        target,
+        u128::MAX
+    )))
+}
+
+#[cfg(not(any(feature = "opencl", feature = "cuda")))]
+pub fn run_pow_gpu(
+    _: CommitBuffer,
+    _: Target,
+    _: &MineOptions,
+    _: &dyn ProgressSink,
+) -> Result<Mined> {
+    Err(Error::msg(
+        "git-power was built without GPU support, rebuild it with `--features cuda` or \
+         `--features opencl` to use --gpu",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{bits, commit, COMMIT};
+
+    /// Keeps the number of zeros of every improvement it's told about.
+    #[derive(Default)]
+    struct Recorder(std::sync::Mutex<Vec<u16>>);
+
+    impl ProgressSink for Recorder {
+        fn on_improvement(&self, _: &[u8], zeros: u16) {
+            self.0.lock().unwrap().push(zeros);
+        }
+
+        fn on_done(&self, _: &Stats) {}
+    }
+
+    /// The commit with its nonce header taken out again, which should be exactly what it was
+    /// before.
+    fn without_nonce(buf: &CommitBuffer) -> Vec<u8> {
+        let data = buf.data();
+        let nonce = buf.nonce_range();
+        let header = [line_ending(data), b"nonce "].concat();
+        assert!(data[..nonce.start].ends_with(&header));
+        [&data[..nonce.start - header.len()], &data[nonce.end..]].concat()
+    }
+
+    #[test]
+    fn commits_need_not_be_utf8() {
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A U Thor <author@example.com> 1700000000 +0000\n\
+            committer J\xf6rg \xff <joerg@example.com> 1700000000 +0000\n\
+            \n\
+            Message\n";
+        let buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+        assert_eq!(without_nonce(&buf), commit);
+        let mined = buf.with_nonce(0x1234);
+        assert_eq!(without_nonce(&mined), commit);
+        let again = CommitBuffer::new(mined.data(), NonceLocation::Header).unwrap();
+        assert_eq!(again.data(), buf.data());
+    }
+
+    #[test]
+    fn a_nonce_in_the_message_is_left_alone() {
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A U Thor <author@example.com> 1700000000 +0000\n\
+            committer A U Thor <author@example.com> 1700000000 +0000\n\
+            \n\
+            Fix nonce handling\n\
+            \n\
+            nonce AAAA is not a header down here\n";
+        let buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+        let data = buf.data();
+        assert_eq!(buf.nonce_range().end, find(data, b"\n\n").unwrap());
+        assert!(data.ends_with(b"\n\nFix nonce handling\n\nnonce AAAA is not a header down here\n"));
+        assert_eq!(without_nonce(&buf), commit);
+    }
+
+    /// `COMMIT` signed with the given armor, every line of which goes on a continuation line of
+    /// the `gpgsig` header.
+    fn signed(armor: &[&str]) -> Vec<u8> {
+        let mut commit = COMMIT.to_vec();
+        let header_end = find(&commit, b"\n\n").unwrap();
+        let header = format!("\ngpgsig {}", armor.join("\n "));
+        insert_bytes(&mut commit, header_end, header.as_bytes());
+        commit
+    }
+
+    /// A signature of `COMMIT` made with `git commit -S`, from GnuPG 2.2.
+    const PGP_SIGNATURE: &[&str] = &[
+        "-----BEGIN PGP SIGNATURE-----",
+        "",
+        "iIkEABYIADEWIQRWxaLiVauxyTYZyZpb6H8ufTm4TQUCas+DexMcYXV0aG9yQGV4",
+        "YW1wbGUuY29tAAoJEFvofy59ObhNcFsBAK1h6BGYG33DHzwS4VxBBHNXaQ+Gt+Ss",
+        "yk5P8fQi792LAP0W9t3UkvgJmGXgE5+AdCUiuHjKEGM3Yn70qvAyeMb0Dw==",
+        "=9fnC",
+        "-----END PGP SIGNATURE-----",
+    ];
+
+    #[test]
+    fn signed_commits_get_a_nonce_armor_header() {
+        let commit = signed(PGP_SIGNATURE);
+        let buf = CommitBuffer::new(&commit, NonceLocation::Header).unwrap();
+        let data = buf.data();
+        let nonce = buf.nonce_range();
+        assert!(data[..nonce.start].ends_with(b"gpgsig -----BEGIN PGP SIGNATURE-----\n Nonce: "));
+        assert!(data[nonce.end..].starts_with(b"\n \n iIkEABYI"));
+        // Nothing but the armor header was added, which signature checks ignore
+        let header = nonce.start - b"\n Nonce: ".len()..nonce.end;
+        assert_eq!(
+            [&data[..header.start], &data[header.end..]].concat(),
+            commit
+        );
+        // Mining it again takes over the armor header rather than adding another
+        let again = CommitBuffer::new(buf.with_nonce(0x1234).data(), NonceLocation::Header);
+        assert_eq!(again.unwrap().data(), data);
+        // Unlike the signature, the message can't change
+        assert!(CommitBuffer::new(&commit, NonceLocation::Trailer).is_err());
+
+        // A payload line starting like the header isn't mistaken for one, even though that
+        // makes for an invalid signature here
+        let mut armor = PGP_SIGNATURE.to_vec();
+        armor[3] = "NonceDHzwS4VxBBHNXaQ+Gt+SsYW1wbGUuY29tAAoJEFvofy59ObhNcFsBAK1h6BGYG3";
+        let commit = signed(&armor);
+        let buf = CommitBuffer::new(&commit, NonceLocation::Header).unwrap();
+        let data = buf.data();
+        assert!(data[..buf.nonce_range().start].ends_with(b"SIGNATURE-----\n Nonce: "));
+        assert!(find(data, armor[3].as_bytes()).is_some());
+    }
+
+    #[test]
+    fn the_nonce_follows_existing_armor_headers() {
+        let mut armor = PGP_SIGNATURE.to_vec();
+        armor.insert(1, "Version: GnuPG v1");
+        armor.insert(2, "Comment: https://gnupg.org");
+        let buf = CommitBuffer::new(&signed(&armor), NonceLocation::Header).unwrap();
+        let data = buf.data();
+        let nonce = buf.nonce_range();
+        assert!(data[..nonce.start].ends_with(b"\n Comment: https://gnupg.org\n Nonce: "));
+        assert!(data[nonce.end..].starts_with(b"\n \n iIkEABYI"));
+
+        // Without the blank line before the payload, one is added after the nonce
+        armor.remove(3);
+        let commit = signed(&armor);
+        let buf = CommitBuffer::new(&commit, NonceLocation::Header).unwrap();
+        let data = buf.data();
+        let nonce = buf.nonce_range();
+        assert!(data[..nonce.start].ends_with(b"\n Comment: https://gnupg.org\n Nonce: "));
+        assert!(data[nonce.end..].starts_with(b"\n \n iIkEABYI"));
+        assert_eq!(
+            data.len(),
+            commit.len() + b"\n Nonce: \n ".len() + NONCE_LENGTH
+        );
+    }
+
+    #[test]
+    fn ssh_and_x509_signatures_cannot_carry_a_nonce() {
+        let ssh = [
+            "-----BEGIN SSH SIGNATURE-----",
+            "U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAgVBq8mJ5yIzq4ocBdcJuQ6V",
+            "-----END SSH SIGNATURE-----",
+        ];
+        let x509 = [
+            "-----BEGIN SIGNED MESSAGE-----",
+            "MIAGCSqGSIb3DQEHAqCAMIACAQExDzANBglghkgBZQMEAgEFADCABgkqhkiG9w0BBwEA",
+            "-----END SIGNED MESSAGE-----",
+        ];
+        for (armor, kind) in [(ssh, "an SSH"), (x509, "an x509")] {
+            let err = CommitBuffer::new(&signed(&armor), NonceLocation::Header)
+                .err()
+                .unwrap()
+                .to_string();
+            assert!(err.starts_with(&format!("Cannot add a nonce to {} signature", kind)));
+            assert!(err.contains("--nonce-location trailer"));
+        }
+    }
+
+    #[test]
+    fn an_existing_nonce_is_replaced_in_place() {
+        // Longer than ours, as if mined by another tool
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A U Thor <author@example.com> 1700000000 +0000\n\
+            committer A U Thor <author@example.com> 1700000000 +0000\n\
+            nonce 0123456789abcdef0123456789abcdef0123456789abcdef\n\
+            \n\
+            Message\n";
+        let mut buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+        buf.write_nonce(u128::MAX);
+        let data = buf.data();
+        let eol = line_ending(data);
+        assert_eq!(
+            parse_headers(data, eol).len(),
+            parse_headers(commit, eol).len()
+        );
+        assert!(data.ends_with(b"\nnonce PPPPPPPPPPPPPPPPPPPPPPPPPPPPPPPP\n\nMessage\n"));
+        assert_eq!(&data[buf.nonce_range()], buf.nonce());
+        assert_eq!(data.len(), commit.len() - 48 + NONCE_LENGTH);
+    }
+
+    #[test]
+    fn commits_need_not_end_in_a_newline() {
+        let commit = COMMIT.strip_suffix(b"\n").unwrap();
+        let buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+        assert_eq!(without_nonce(&buf), commit);
+        assert!(buf.data().ends_with(b"\n\nInitial commit"));
+
+        let buf = CommitBuffer::new(commit, NonceLocation::Trailer).unwrap();
+        let trailer = b"Initial commit\n\nNonce: AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\n";
+        assert_eq!(buf.data(), [&commit[..commit.len() - 14], trailer].concat());
+
+        // Nor need there be anything after the headers, in which case the nonce ends it
+        let headers = &COMMIT[..find(COMMIT, b"\n\n").unwrap()];
+        let buf = CommitBuffer::new(headers, NonceLocation::Header).unwrap();
+        assert_eq!(buf.nonce_range().end, buf.data().len());
+        assert_eq!(without_nonce(&buf), headers);
+    }
+
+    #[test]
+    fn crlf_commits_keep_their_line_endings() {
+        let commit = String::from_utf8_lossy(COMMIT).replace('\n', "\r\n");
+        let commit = commit.as_bytes();
+        let buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+        let data = buf.data();
+        let nonce = buf.nonce_range();
+        assert!(data[..nonce.start].ends_with(b"+0000\r\nnonce "));
+        assert!(data[nonce.end..].starts_with(b"\r\n\r\nInitial commit\r\n"));
+        assert_eq!(without_nonce(&buf), commit);
+
+        let buf = CommitBuffer::new(commit, NonceLocation::Trailer).unwrap();
+        let trailer = b"Initial commit\r\n\r\nNonce: AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\r\n";
+        assert!(buf.data().ends_with(trailer));
+        assert!(!buf
+            .data()
+            .windows(2)
+            .any(|pair| pair[1] == b'\n' && pair[0] != b'\r'));
+    }
+
+    #[test]
+    fn merges_keep_both_parents() {
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            parent 1111111111111111111111111111111111111111\n\
+            parent 2222222222222222222222222222222222222222\n\
+            author A U Thor <author@example.com> 1700000000 +0000\n\
+            committer A U Thor <author@example.com> 1700000000 +0000\n\
+            \n\
+            Merge branch 'topic'\n";
+        let buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+        let data = buf.data();
+        let headers = parse_headers(data, b"\n");
+        let keys: Vec<_> = (headers.iter())
+            .map(|header| {
+                &data[header.start..header.start + find(&data[header.clone()], b" ").unwrap()]
+            })
+            .collect();
+        let expected: [&[u8]; 6] = [
+            b"tree",
+            b"parent",
+            b"parent",
+            b"author",
+            b"committer",
+            b"nonce",
+        ];
+        assert_eq!(keys, expected);
+        assert_eq!(without_nonce(&buf), commit);
+    }
+
+    #[test]
+    fn nonce_header_goes_after_the_other_headers() {
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A U Thor <author@example.com> 1700000000 +0000\n\
+            committer A U Thor <author@example.com> 1700000000 +0000\n\
+            encoding ISO-8859-1\n\
+            \n\
+            Message\n";
+        let buf = CommitBuffer::new(commit, NonceLocation::Header).unwrap();
+        let data = buf.data();
+        let nonce = buf.nonce_range();
+        assert!(data[..nonce.start].ends_with(b"encoding ISO-8859-1\nnonce "));
+        assert!(data[nonce.end..].starts_with(b"\n\nMessage\n"));
+        // Mining the same commit again takes over its nonce rather than adding another
+        let again = CommitBuffer::new(data, NonceLocation::Header).unwrap();
+        assert_eq!(again.data(), data);
+    }
+
+    #[test]
+    fn hashes_in_the_hot_loop_are_the_commit_ids() {
+        use sha1::Digest;
+        let buf = commit();
+        let mut midstate = buf.midstate::<Sha1>();
+        // Starting right before a carry in the nonce, to go through one as well
+        let start = 16 * 16 - 3;
+        let mut counter = NonceCounter::new(start);
+        for round in 0..3 {
+            let hashes = hash_lanes::<Sha1>(&mut midstate, &mut counter);
+            for (lane, hash) in hashes.iter().enumerate() {
+                let nonce = start + (round * LANES + lane) as u128;
+                let data = buf.with_nonce(nonce).data().to_vec();
+                let mut object = format!("commit {}\0", data.len()).into_bytes();
+                object.extend_from_slice(&data);
+                assert_eq!(hash[..], Sha1::digest(&object)[..], "nonce {}", nonce);
+            }
+        }
+    }
+
+    #[test]
+    fn improvements_only_go_up() {
+        // Many more threads than there are cores, racing to report hashes that are easy to find
+        let pool = Pool::new(16, false).unwrap();
+        for seed in 0..20 {
+            let buf = commit();
+            let options = MineOptions {
+                seed: Some(seed),
+                ..MineOptions::default()
+            };
+            let sink = Recorder::default();
+            let mined = run_pow::<Sha1>(&pool, buf, bits(12), &options, &sink).unwrap();
+            let zeros = sink.0.into_inner().unwrap();
+            assert!(
+                zeros.windows(2).all(|pair| pair[0] < pair[1]),
+                "{:?}",
+                zeros
+            );
+            // The hash mined is always the last one shown
+            assert_eq!(zeros.last(), Some(&num_leading_zero_bits(&mined.hash)));
+        }
+    }
+
+    #[test]
+    fn attempts_are_about_as_many_as_expected() {
+        // Each run takes 2^10 attempts on average, but any one of them varies a lot, so only
+        // lots of them together have to come close
+        const RUNS: u64 = 64;
+        let pool = Pool::new(4, false).unwrap();
+        let mut attempts = 0;
+        for seed in 0..RUNS {
+            let buf = commit();
+            let options = MineOptions {
+                seed: Some(seed),
+                ..MineOptions::default()
+            };
+            let mined = run_pow::<Sha1>(&pool, buf, bits(10), &options, &NoProgress).unwrap();
+            attempts += mined.attempts;
+        }
+        let expected = bits(10).expected_attempts(160);
+        let mean = attempts as f64 / RUNS as f64;
+        assert!(mean > expected / 2.0 && mean < expected * 2.0, "{}", mean);
+    }
+
+    #[test]
+    fn dropping_the_iterator_stops_mining() {
+        let buf = commit();
+        let pool = Pool::new(2, false).unwrap();
+        // Without a time limit, this would go on practically forever
+        let mut events = run_pow_iter::<Sha1>(pool, buf, bits(160), MineOptions::default());
+        assert!(matches!(events.next(), Some(Ok(PowEvent::Improvement(..)))));
+        let start = Instant::now();
+        // Only returns once the pool and its workers are gone
+        drop(events);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn cancelling_fails_the_search() {
+        let buf = commit();
+        let pool = Pool::new(2, false).unwrap();
+        let cancel = Arc::new(AtomicBool::new(true));
+        let options = MineOptions {
+            cancel: Some(cancel),
+            ..MineOptions::default()
+        };
+        let result = run_pow::<Sha1>(&pool, buf, bits(160), &options, &NoProgress);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn running_out_of_nonces_fails_the_search() {
+        let buf = commit();
+        let pool = Pool::new(2, false).unwrap();
+        let options = MineOptions::default();
+        let result = search::<Sha1>(&pool, buf, bits(160), &options, &NoProgress, 3);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "No solution found for 160 leading zero bits in the searched space of 196608 nonces"
+        );
+    }
+
+    #[test]
+    fn object_formats_are_named_like_git_does() {
+        // As printed by `git rev-parse --show-object-format`
+        assert!("sha1".parse::<ObjectFormat>().unwrap() == ObjectFormat::Sha1);
+        assert!("sha256".parse::<ObjectFormat>().unwrap() == ObjectFormat::Sha256);
+        assert!("sha512".parse::<ObjectFormat>().is_err());
+        assert_eq!(ObjectFormat::Sha1.bits(), 160);
+        assert_eq!(ObjectFormat::Sha256.bits(), 256);
+    }
+
+    #[test]
+    fn zero_bits_are_counted_from_either_end() {
+        let cases: [(&[u8], u16, u16); 6] = [
+            (&[0x00, 0x00], 16, 16),
+            (&[0x01, 0x80], 7, 7),
+            (&[0x00, 0x10, 0x08, 0x00], 11, 11),
+            (&[0xff, 0xff], 0, 0),
+            (&[0x80, 0x00, 0x00, 0x01], 0, 0),
+            (&[0x0f, 0xf0, 0x00], 4, 12),
+        ];
+        for (hash, leading, trailing) in cases {
+            assert_eq!(num_leading_zero_bits(hash), leading, "{:02x?}", hash);
+            assert_eq!(num_trailing_zero_bits(hash), trailing, "{:02x?}", hash);
+        }
+    }
+
+    #[test]
+    fn one_pool_mines_one_commit_after_another() {
+        let pool = Pool::new(2, false).unwrap();
+        let thread_ids = || {
+            let ids = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let sink = Arc::clone(&ids);
+            pool.broadcast(move |_| {
+                sink.lock().unwrap().push(std::thread::current().id());
+                Ok(())
+            })
+            .wait()
+            .unwrap();
+            let mut ids = ids.lock().unwrap().clone();
+            ids.sort_by_key(|id| format!("{:?}", id));
+            ids
+        };
+        let before = thread_ids();
+        for message in ["First\n", "Second\n"] {
+            let commit = [&COMMIT[..COMMIT.len() - 15], message.as_bytes()].concat();
+            let buf = CommitBuffer::new(&commit, NonceLocation::Header).unwrap();
+            let options = MineOptions::default();
+            let mined = run_pow::<Sha1>(&pool, buf, bits(8), &options, &NoProgress).unwrap();
+            assert!(num_leading_zero_bits(&mined.hash) >= 8);
+            assert!(mined.commit.data().ends_with(message.as_bytes()));
+        }
+        // The same threads did all of it
+        assert_eq!(thread_ids(), before);
+    }
+
+    #[test]
+    fn waiting_wakes_up_without_any_messages() {
+        /// Counts the hashrate samples, which are taken between messages from the workers.
+        #[derive(Default)]
+        struct Samples(AtomicUsize);
+
+        impl ProgressSink for Samples {
+            fn on_improvement(&self, _: &[u8], _: u16) {
+                panic!("Nothing comes closer to a pinned byte than anything else");
+            }
+
+            fn on_done(&self, _: &Stats) {}
+
+            fn on_hashrate(&self, _: f64) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        // Not a single hash gets reported, so the workers stay quiet the whole time
+        let target = Target {
+            bytes: (0..8)
+                .map(|index| target::BytePin { index, value: 0 })
+                .collect(),
+            ..Target::default()
+        };
+        let buf = commit();
+        let pool = Pool::new(2, false).unwrap();
+        let options = MineOptions {
+            max_time: Some(HASHRATE_INTERVAL * 2 + WAKEUP_INTERVAL),
+            ..MineOptions::default()
+        };
+        let sink = Samples::default();
+        let start = Instant::now();
+        let result = run_pow::<Sha1>(&pool, buf, target, &options, &sink);
+        assert!(result.is_err());
+        // The time limit is noticed when it's up rather than only once a message arrives,
+        // and the hashrate gets sampled along the way
+        let elapsed = start.elapsed();
+        assert!(elapsed >= options.max_time.unwrap(), "{:?}", elapsed);
+        assert!(
+            elapsed < options.max_time.unwrap() + 4 * WAKEUP_INTERVAL,
+            "{:?}",
+            elapsed
+        );
+        assert!(sink.0.load(Ordering::Relaxed) >= 1);
+    }
+
+    #[test]
+    fn nothing_to_look_for_takes_the_first_nonce() {
+        let pool = Pool::new(1, false).unwrap();
+        for target in [
+            bits(0),
+            Target {
+                trailing_bits: 1,
+                ..bits(0)
+            },
+        ] {
+            let buf = commit();
+            let first = buf.with_nonce(0).data().to_vec();
+            let trivial = target.is_trivial();
+            let mined =
+                run_pow::<Sha1>(&pool, buf, target, &MineOptions::default(), &NoProgress).unwrap();
+            if trivial {
+                assert_eq!(mined.commit.data(), &first[..]);
+            }
+            assert!(mined.attempts <= 64 * LANES as u128, "{}", mined.attempts);
+        }
+    }
+
+    #[test]
+    fn hashrates_are_in_millions_per_second() {
+        assert_eq!(to_mhps(3_000_000.0, Duration::from_millis(1500)), 2.0);
+        assert_eq!(to_mhps(0.0, Duration::from_secs(1)), 0.0);
+        // Rather than infinitely fast
+        assert_eq!(to_mhps(1000.0, Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn seconds_are_shown_in_the_largest_unit_reached() {
+        assert_eq!(format_seconds(0.0), "0.000 seconds");
+        assert_eq!(format_seconds(59.9994), "59.999 seconds");
+        assert_eq!(format_seconds(60.0), "1.0 minutes");
+        assert_eq!(format_seconds(5400.0), "1.5 hours");
+        assert_eq!(format_seconds(3.0 * 24.0 * 3600.0), "3.0 days");
+        assert_eq!(format_seconds(1e12), "31709.8 years");
+    }
+}
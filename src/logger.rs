@@ -0,0 +1,143 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// Diagnostics about what git-power is up to, on top of the progress it shows anyway. Each -v
+// shows more of them, and like the progress, they go to stderr, stamped with the time since
+// git-power started. For finer control, `RUST_LOG` takes a comma-separated list of levels
+// like `env_logger` does, each either applying to everything or, as `target=level`, to the
+// targets starting with the given module path, e.g. `RUST_LOG=info,git_power::pool=trace`.
+
+struct Logger {
+    start_time: Instant,
+    /// The levels to log at, each applying to the targets starting with its prefix. The longest
+    /// matching prefix wins.
+    directives: Vec<(String, LevelFilter)>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+impl Logger {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(LevelFilter::Off, |&(_, level)| level)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "[{:.3}s] {}: {}",
+                self.start_time.elapsed().as_secs_f64(),
+                record.level().as_str().to_lowercase(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets up logging, with `verbosity` being the number of times -v was given. If `RUST_LOG` is
+/// set, it's used instead.
+pub fn init(verbosity: u8) {
+    let level = match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    // Unless asked for, only our own messages are of interest, and not those of any
+    // dependencies
+    let directives = match std::env::var("RUST_LOG") {
+        Ok(spec) => parse_spec(&spec),
+        Err(_) => vec![("git_power".to_string(), level)],
+    };
+    let max_level = directives
+        .iter()
+        .map(|&(_, level)| level)
+        .max()
+        .unwrap_or(LevelFilter::Off);
+    let logger = LOGGER.get_or_init(|| Logger {
+        start_time: Instant::now(),
+        directives,
+    });
+    // This only fails if a logger was set already, which it never is
+    let _ = log::set_logger(logger);
+    log::set_max_level(max_level);
+}
+
+fn parse_spec(spec: &str) -> Vec<(String, LevelFilter)> {
+    let mut directives = Vec::new();
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let parsed = match directive.split_once('=') {
+            Some((target, level)) => level.parse().ok().map(|level| (target.to_string(), level)),
+            // A lone level applies to everything, while a lone target gets everything logged
+            None => Some(match directive.parse() {
+                Ok(level) => (String::new(), level),
+                Err(_) => (directive.to_string(), LevelFilter::Trace),
+            }),
+        };
+        match parsed {
+            Some(parsed) => directives.push(parsed),
+            None => eprintln!("warning: ignoring invalid RUST_LOG directive {}", directive),
+        }
+    }
+    directives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logger(spec: &str) -> Logger {
+        Logger {
+            start_time: Instant::now(),
+            directives: parse_spec(spec),
+        }
+    }
+
+    #[test]
+    fn specs_are_lists_of_levels() {
+        assert_eq!(
+            parse_spec("info, git_power::pool=trace,,git2=OFF"),
+            [
+                (String::new(), LevelFilter::Info),
+                ("git_power::pool".to_string(), LevelFilter::Trace),
+                ("git2".to_string(), LevelFilter::Off),
+            ]
+        );
+        // A lone target gets everything logged
+        assert_eq!(
+            parse_spec("git_power"),
+            [("git_power".to_string(), LevelFilter::Trace)]
+        );
+        assert_eq!(parse_spec(""), []);
+    }
+
+    #[test]
+    fn invalid_levels_are_left_out() {
+        assert_eq!(
+            parse_spec("git_power=loud,debug"),
+            [(String::new(), LevelFilter::Debug)]
+        );
+    }
+
+    #[test]
+    fn the_longest_matching_prefix_wins() {
+        let nested = logger("warn,git_power=info,git_power::pool=trace");
+        assert_eq!(nested.level("git_power::pool"), LevelFilter::Trace);
+        assert_eq!(nested.level("git_power::repo"), LevelFilter::Info);
+        assert_eq!(nested.level("git2"), LevelFilter::Warn);
+        // Without a level for everything, the rest isn't logged
+        assert_eq!(logger("git_power").level("sha1"), LevelFilter::Off);
+    }
+}
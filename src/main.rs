@@ -1,229 +1,815 @@
 use anyhow::{Error, Result};
-use git2::{ObjectType, Oid, Repository, ResetType};
-use sha1::{Digest, Sha1};
-use std::convert::TryInto;
-use std::fmt;
-use std::io::{stdout, Write};
-use std::sync::atomic::*;
-use std::sync::{mpsc, Arc};
-use std::time::Instant;
+use config_file::Defaults;
+use git_power::hasher::{PowHasher, Sha1Backend};
+use git_power::pool::Pool;
+use git_power::regex::Regex;
+use git_power::repo::{self, to_hex, Repo};
+use git_power::target::{
+    Base32Pattern, BytePin, HexPattern, Leet, Mask, PrefixSet, Target, UniqueAbbrev,
+};
+use git_power::{
+    color, format_seconds, is_signed, measure_hashrate, num_leading_zero_bits, patch,
+    rewrite_descendants, run_pow, run_pow_gpu, signal, to_mhps, CommitBuffer, MineOptions, Mined,
+    NoProgress, NonceLocation, ObjectFormat, ProgressSink, Sha1, Sha256,
+};
+use log::info;
+use progress::TerminalProgress;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use structopt::clap::{AppSettings, ArgMatches, Shell};
 use structopt::StructOpt;
 
-const NONCE_LENGTH: usize = 32;
+mod config_file;
+mod hook;
+mod logger;
+mod man;
+mod progress;
+
+/// A seed for when none was given, so that separate runs don't search the same nonces. The
+/// standard library already seeds its hash maps from the OS, so that's where it comes from.
+fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
 
 #[derive(StructOpt)]
 struct Config {
-    #[structopt(short, long, default_value = "32")]
-    bits: u16,
+    #[structopt(flatten)]
+    target: TargetConfig,
 
-    #[structopt(short, long)]
+    #[structopt(short, long, parse(try_from_str = parse_threads))]
     threads: Option<u8>,
+
+    /// Pick the number of threads by measuring the hashrate with a few different ones first,
+    /// unless `--threads` is given
+    #[structopt(long)]
+    auto_threads: bool,
+
+    /// Pin each worker thread to a CPU of its own, so that they aren't moved around by the
+    /// scheduler
+    #[structopt(long)]
+    pin: bool,
+
+    /// Number of seconds to mine for at most. If the target isn't met by then, the hash that
+    /// came closest to it is used instead
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    max_time: Option<Duration>,
+
+    /// File to record the best hash so far and how much of the nonces were searched in every
+    /// so often, so that it's known how far mining got if it's cut short
+    #[structopt(long, parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+
+    /// Number of seconds between updates of the --checkpoint file
+    #[structopt(long, default_value = "10", parse(try_from_str = parse_seconds))]
+    checkpoint_interval: Duration,
+
+    /// Mine the commit, but only print the nonce and hash it ends up with, without writing it
+    /// to the repository or moving HEAD
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Write the mined commit to the repository and print its hash, but leave HEAD where it
+    /// is
+    #[structopt(long, conflicts_with = "dry-run")]
+    no_reset: bool,
+
+    /// Instead of mining, print how many attempts and how much time meeting the target is
+    /// expected to take, based on a short measurement of the hashrate
+    #[structopt(long)]
+    estimate: bool,
+
+    /// Start from the nonces picked by this seed, rather than a random one. Runs with the same
+    /// seed and commit search the same nonces in the same order
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Mine on a GPU using CUDA or OpenCL, which requires git-power to be built with
+    /// `--features cuda` or `--features opencl`. Only SHA-1 and targets of leading zeros are
+    /// supported
+    #[structopt(long)]
+    gpu: bool,
+
+    #[structopt(long, default_value = "header", possible_values = &["header", "trailer"])]
+    nonce_location: NonceLocation,
+
+    #[structopt(long, possible_values = &["sha1", "sha256"])]
+    object_format: Option<ObjectFormat>,
+
+    /// How to report the result. With `json`, nothing but a JSON object describing the mined
+    /// commit is printed once done
+    #[structopt(long, default_value = "human", possible_values = &["human", "json"])]
+    format: OutputFormat,
+
+    /// Print nothing but the hash of the mined commit, for use in scripts
+    #[structopt(long, conflicts_with = "format")]
+    porcelain: bool,
+
+    /// Write a diff between the original commit object and the mined one to the given file,
+    /// showing what changed about it
+    #[structopt(long, parse(from_os_str))]
+    patch: Option<PathBuf>,
+
+    /// Read the commit object to mine from stdin and print the mined one to stdout, without
+    /// touching any repository
+    #[structopt(long, conflicts_with_all = &["format", "porcelain", "no-reset", "estimate"])]
+    stdin: bool,
+
+    /// Print the raw mined commit object to stdout instead of writing it to the repository,
+    /// implying --dry-run
+    #[structopt(long, conflicts_with_all = &["format", "porcelain", "no-reset"])]
+    stdout: bool,
+
+    /// Don't print any progress or statistics, only errors
+    #[structopt(short, long)]
+    quiet: bool,
+
+    /// Log what's going on to stderr: the threads and backend used with -v, the nonces each
+    /// thread takes on with -vv, and every new best hash with -vvv
+    #[structopt(short, long, parse(from_occurrences), conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Implementation of SHA-1 to use, instead of the fastest one the CPU supports
+    #[structopt(long, possible_values = &["sha-ni", "avx2", "sse2", "scalar"])]
+    sha1_backend: Option<Sha1Backend>,
+
+    /// Rewrite the commits between the mined one and HEAD on top of it when mining a commit
+    /// other than HEAD, since their parents change along with its hash
+    #[structopt(long)]
+    rewrite_descendants: bool,
+
+    /// The commit to mine, which needs to be HEAD or one of its ancestors
+    #[structopt(default_value = "HEAD")]
+    revspec: String,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Clone)]
-struct CommitBuffer {
-    buf: String,
-    header_len: usize,
-    nonce_start: usize,
-    nonce_end: usize,
-}
-
-impl CommitBuffer {
-    fn new(buf: &[u8]) -> Result<Self> {
-        let mut buf = String::from_utf8(buf.iter().cloned().collect())?;
-
-        // The nonce will be in different locations depending on whether the commit is signed.
-        //  - If it isn't, then we add the nonce as an additional field in the commit details,
-        //    after the committer date.
-        //  - If the commit is signed, then we add the Nonce as a header *inside* the GPG sig.
-        //    Since the signature is only on the commit contents, the commit will stay signed.
-        //    Any proper GPG client will ignore this header and verify the signature just fine.
-        let start = match buf.find("-----BEGIN PGP SIGNATURE-----") {
-            Some(pgp_idx) => match buf[pgp_idx..].find("Nonce") {
-                Some(idx) => pgp_idx + idx + 7,
-                None => {
-                    let pgp_header_end = pgp_idx
-                        + buf[pgp_idx..]
-                            .find("\n ")
-                            .ok_or("Malformed PGP header")
-                            .map_err(Error::msg)?;
-                    buf.insert_str(pgp_header_end, "\nNonce: ");
-                    pgp_header_end + 8
-                }
-            },
-            None => {
-                let header_end = buf
-                    .find("\n\n")
-                    .ok_or("Malformed commit")
-                    .map_err(Error::msg)?;
-                match buf.find("nonce") {
-                    Some(idx) => idx + 6,
-                    None => {
-                        buf.insert_str(header_end, "\nnonce ");
-                        header_end + 7
-                    }
-                }
-            }
-        };
+#[derive(StructOpt)]
+enum Command {
+    /// Checks whether a commit already meets the target, without mining anything
+    Verify {
+        /// The commit to check
+        #[structopt(default_value = "HEAD")]
+        revspec: String,
 
-        // Here we insert the initial value of the nonce. In case the nonce already exists
-        // and is shorter than the one we're inserting, we do a `replace_range`.
-        let line_end = start
-            + buf[start..]
-                .find("\n")
-                .ok_or("Malformed Nonce")
-                .map_err(Error::msg)?;
-        if line_end - start != NONCE_LENGTH {
-            buf.replace_range(start..line_end, "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
-        }
-
-        // When git hashes an object, it prepends a header to the data which inclues the type
-        // of the object, as well as its size. We opt to call out to a faster SHA-1 library,
-        // so we need to prepend the header ourselves.
-        let commit_header = format!("commit {}\0", buf.len());
-        buf.insert_str(0, &commit_header);
-        let header_len = commit_header.len();
-        Ok(Self {
-            buf,
-            header_len,
-            nonce_start: start + header_len,
-            nonce_end: start + NONCE_LENGTH + header_len,
-        })
+        #[structopt(flatten)]
+        target: Box<TargetConfig>,
+    },
+
+    /// Measures the hashrate on a made-up commit, without touching any repository
+    Bench {
+        #[structopt(short, long, parse(try_from_str = parse_threads))]
+        threads: Option<u8>,
+
+        /// Number of seconds to hash for
+        #[structopt(short, long, default_value = "5", parse(try_from_str = parse_seconds))]
+        seconds: Duration,
+    },
+
+    /// Sets up git hooks running git-power
+    Hook(HookCommand),
+
+    /// Prints a completion script for the given shell
+    #[structopt(setting = AppSettings::Hidden)]
+    Completions {
+        #[structopt(possible_values = &Shell::variants())]
+        shell: Shell,
+    },
+
+    /// Prints a man page
+    #[structopt(setting = AppSettings::Hidden)]
+    Man,
+}
+
+#[derive(StructOpt)]
+enum HookCommand {
+    /// Installs a post-commit hook that mines every new commit, or a pre-push hook that
+    /// rejects commits falling short
+    Install {
+        /// Number of leading zero bits to mine to, or to require with --pre-push [default:
+        /// whatever git-power defaults to when the hook runs]
+        #[structopt(short, long)]
+        bits: Option<u16>,
+
+        /// Install a pre-push hook rejecting the push if any commit being pushed has fewer
+        /// than --bits leading zero bits, rather than mining anything
+        #[structopt(long, requires = "bits")]
+        pre_push: bool,
+
+        /// Replace the hook if there already is one
+        #[structopt(short, long)]
+        force: bool,
+    },
+}
+
+// The options making up the target, which are shared between mining and verifying
+#[derive(StructOpt)]
+struct TargetConfig {
+    /// Number of leading zero bits the hash needs to have [default: 32, or 0 if another
+    /// target is given]
+    #[structopt(short, long)]
+    bits: Option<u16>,
+
+    /// Number of leading zero hex digits the hash needs to have, as a more readable
+    /// alternative to --bits
+    #[structopt(short, long, conflicts_with = "bits")]
+    zeros: Option<u16>,
+
+    /// Number of trailing zero bits the hash needs to have
+    #[structopt(long)]
+    trailing_bits: Option<u16>,
+
+    /// Number of identical hex digits the hash needs to start with, like 0000 or cccc
+    #[structopt(long)]
+    repeat: Option<usize>,
+
+    /// Hex digits the hash needs to start with, which may be an odd number of digits. If given
+    /// multiple times, any one of them will do
+    #[structopt(long, number_of_values = 1)]
+    prefix: Vec<HexPattern>,
+
+    /// File with one prefix per line, any one of which the hash may start with. Empty lines and
+    /// lines starting with # are ignored
+    #[structopt(long, parse(from_os_str))]
+    targets: Option<PathBuf>,
+
+    /// Hex digits the hash needs to end with, which may be an odd number of digits
+    #[structopt(long)]
+    suffix: Option<HexPattern>,
+
+    /// Digits of Crockford's base32 the base32 encoding of the hash needs to start with
+    #[structopt(long)]
+    base32_prefix: Option<Base32Pattern>,
+
+    /// Comma-separated words the hash may start with, spelled in hex using --leet
+    #[structopt(long, use_delimiter = true)]
+    word: Vec<String>,
+
+    /// Comma-separated letter=digit pairs used to spell words in hex
+    #[structopt(long, default_value = "o=0,i=1,l=1,z=2,s=5,t=7,g=9")]
+    leet: Leet,
+
+    /// Hex digits the hash needs to contain somewhere
+    #[structopt(long)]
+    contains: Option<HexPattern>,
+
+    /// Hex digits selecting which bits of the hash need to be equal to those of --value
+    #[structopt(long, requires = "value")]
+    mask: Option<HexPattern>,
+
+    /// Hex digits the bits of the hash selected by --mask need to be equal to
+    #[structopt(long, requires = "mask")]
+    value: Option<HexPattern>,
+
+    /// Byte of the hash at a given index that needs to have a given value, written as
+    /// <index>=<hex byte>. May be given multiple times
+    #[structopt(long = "byte", number_of_values = 1, value_name = "index=byte")]
+    bytes: Vec<BytePin>,
+
+    /// Object id the hash needs to sort before, comparing both as big-endian numbers
+    #[structopt(long)]
+    less_than: Option<HexPattern>,
+
+    /// Number of hex digits the hash needs to be abbreviable to without becoming ambiguous
+    /// with any other object in the repository
+    #[structopt(long)]
+    unique_abbrev: Option<usize>,
+
+    /// Regex the lowercase hex representation of the hash needs to match
+    #[structopt(long)]
+    regex: Option<Regex>,
+
+    // Taken from the config files rather than the command line, and used in place of 32
+    #[structopt(skip)]
+    default_bits: Option<u16>,
+}
+
+impl Config {
+    fn num_threads(&self) -> usize {
+        num_threads(self.threads)
+    }
+
+    /// Fills in the defaults from the config files for whatever wasn't given on the command
+    /// line.
+    fn apply_defaults(&mut self, defaults: Defaults, matches: &ArgMatches) {
+        self.threads = self.threads.or(defaults.threads);
+        self.target.default_bits = defaults.bits;
+        if let Some(Command::Verify { target, .. }) = &mut self.command {
+            target.default_bits = defaults.bits;
+        }
+        // These always have a value, so only an explicit one on the command line counts
+        if let Some(location) = defaults.nonce_location {
+            if matches.occurrences_of("nonce-location") == 0 {
+                self.nonce_location = location;
+            }
+        }
+        if let Some(format) = defaults.format {
+            if matches.occurrences_of("format") == 0 && !self.porcelain {
+                self.format = format;
+            }
+        }
     }
 
-    fn write_nonce(&mut self, val: u128) {
-        // Only covers 128/160 bits of entropy. Possible chars: ABCDEFGHIJKLMNOP
-        let mut nonce_bytes = [b'A'; NONCE_LENGTH];
-        for i in 0..NONCE_LENGTH {
-            let idx = (val >> (4 * i)) & 0xF;
-            nonce_bytes[31 - i] = b'A' + idx as u8;
+    fn mine_options(&self) -> MineOptions {
+        MineOptions {
+            max_time: self.max_time,
+            seed: self.seed,
+            checkpoint: self.checkpoint.clone(),
+            checkpoint_interval: self.checkpoint_interval,
+            cancel: None,
         }
-        // SAFETY: The nonce is always valid ASCII, and contain no multi-byte codepoints
-        unsafe { self.buf[self.nonce_start..self.nonce_end].as_bytes_mut() }
-            .copy_from_slice(&nonce_bytes);
     }
 
-    fn data(&self) -> &str {
-        // We just want the commit data itself, minus the prepended metadata header
-        &self.buf[self.header_len..]
+    /// Whether to print progress and statistics meant for people to read.
+    fn human_output(&self) -> bool {
+        self.format == OutputFormat::Human && !self.quiet && !self.porcelain && !self.stdout
     }
 }
 
-impl fmt::Display for CommitBuffer {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.data())
+/// The number of threads to mine with. Without one given, `RAYON_NUM_THREADS` is respected
+/// the way thread pools commonly do, and otherwise there's one per logical core. Anything but a
+/// positive number there counts as it not being set.
+fn num_threads(threads: Option<u8>) -> usize {
+    threads
+        .map(usize::from)
+        .or_else(|| {
+            let threads = std::env::var("RAYON_NUM_THREADS").ok()?;
+            threads.parse().ok().filter(|&threads| threads > 0)
+        })
+        .unwrap_or_else(num_cpus::get)
+}
+
+fn parse_threads(s: &str) -> Result<u8> {
+    match s.parse().map_err(Error::msg)? {
+        0 => Err(Error::msg("--threads must be at least 1")),
+        threads => Ok(threads),
     }
 }
 
-enum PowMessage {
-    Update([u8; 20], u16),
-    Done(CommitBuffer, [u8; 20]),
+fn parse_seconds(s: &str) -> Result<Duration> {
+    let seconds = s.parse().map_err(Error::msg)?;
+    Duration::try_from_secs_f64(seconds).map_err(Error::msg)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Human,
+    Json,
 }
 
-fn num_leading_zero_bits(hash: &[u8]) -> u16 {
-    let mut zeros = 0;
-    for &byte in hash.iter() {
-        zeros += byte.leading_zeros();
-        if byte != 0 {
-            break;
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::msg(format!("Unknown output format: {}", s))),
         }
     }
-    zeros as u16
 }
 
-fn run_pow(commit: CommitBuffer, config: &Config) -> Result<(CommitBuffer, Oid)> {
-    let start_time = Instant::now();
-    let (tx, rx) = mpsc::channel();
-    let stop = Arc::new(AtomicBool::new(false));
-    let num_hashes = Arc::new(AtomicU64::new(0));
-    let max_zeros = Arc::new(AtomicU16::new(0));
-    let target_zeros = config.bits;
-
-    // We divide the range of possible nonces evenly among each thread. Each thread loops
-    // through each nonce in its given range and calculates a hash, and if it satisfies the
-    // POW requirement, sends it to the main thread. When this happens, we stop all other
-    // threads using the AtomicBool `stop`.
-    let num_threads = config.threads.unwrap_or(num_cpus::get() as u8) as u128;
-    let chunk_size = u128::MAX / num_threads;
-    for i in 0..num_threads {
-        let tx = tx.clone();
-        let stop = Arc::clone(&stop);
-        let num_hashes = Arc::clone(&num_hashes);
-        let max_zeros = Arc::clone(&max_zeros);
-        let mut commit = commit.clone();
-        std::thread::spawn(move || -> Result<()> {
-            // Since the part of the commit before the nonce never changes,
-            // we reuse the SHA-1 state computed up to the nonce's location.
-            let mut hasher = Sha1::new();
-            hasher.update(&commit.buf[..commit.nonce_start]);
-            for nonce in i * chunk_size..(i + 1) * chunk_size {
-                // Check if a hash was already found by another thread
-                if stop.load(Ordering::Relaxed) {
-                    break;
-                }
-                num_hashes.fetch_add(1, Ordering::Relaxed);
-
-                // Write the nonce to the commit buffer and hash the buffer
-                commit.write_nonce(nonce);
-                let mut hasher = hasher.clone();
-                hasher.update(&commit.buf[commit.nonce_start..]);
-                let hash: [u8; 20] = hasher.finalize().as_slice().try_into()?;
-
-                // Check against our win condition
-                let num_zeros = num_leading_zero_bits(&hash);
-                if num_zeros > max_zeros.load(Ordering::Relaxed) {
-                    tx.send(PowMessage::Update(hash, num_zeros))?;
-                    max_zeros.store(num_zeros, Ordering::Relaxed);
-                }
-                if num_zeros >= target_zeros {
-                    tx.send(PowMessage::Done(commit, hash))?;
-                    break;
+impl TargetConfig {
+    /// Builds the target, looking up the objects in the repository if needed. The id of the
+    /// commit being checked, if it's in the repository already, can be given so that it doesn't
+    /// count as colliding with itself.
+    fn target(&self, repo: Option<&dyn Repo>, own_id: Option<&[u8]>) -> Result<Target> {
+        let mut prefixes = self.prefix.clone();
+        for word in &self.word {
+            prefixes.push(self.leet.spell(word)?);
+        }
+        if let Some(path) = &self.targets {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|err| Error::msg(format!("Cannot read {}: {}", path.display(), err)))?;
+            for line in contents.lines().map(str::trim) {
+                if !line.is_empty() && !line.starts_with('#') {
+                    prefixes.push(line.parse()?);
                 }
             }
-            Ok(())
-        });
-    }
-    let mut stdout = stdout();
-    loop {
-        match rx.recv()? {
-            PowMessage::Update(hash, num_zeros) => {
-                let hash = Oid::from_bytes(&hash)?;
-                print!(
-                    "\rFound {} ({}/{} leading zeros)",
-                    hash, num_zeros, config.bits
-                );
-                stdout.flush().unwrap();
+        }
+        let mut target = Target {
+            bits: self.bits.or(self.zeros.map(|zeros| 4 * zeros)).unwrap_or(0),
+            trailing_bits: self.trailing_bits.unwrap_or(0),
+            repeat: self.repeat.unwrap_or(0),
+            prefixes: PrefixSet::new(prefixes),
+            suffix: self.suffix.clone(),
+            base32_prefix: self.base32_prefix.clone(),
+            contains: self.contains.clone(),
+            mask: self
+                .mask
+                .clone()
+                .zip(self.value.clone())
+                .map(|(mask, value)| Mask { mask, value }),
+            bytes: self.bytes.clone(),
+            less_than: self.less_than.clone(),
+            unique_abbrev: match self.unique_abbrev {
+                Some(len) => {
+                    let repo = repo.ok_or_else(|| {
+                        Error::msg("--unique-abbrev needs a repository to look up objects in")
+                    })?;
+                    let mut ids = repo.object_ids()?;
+                    ids.retain(|id| Some(id.as_slice()) != own_id);
+                    Some(UniqueAbbrev::new(len, ids))
+                }
+                None => None,
+            },
+            regex: self.regex.clone(),
+        };
+        // Asking for a specific pattern is usually incompatible with the default amount of
+        // leading zeros, so the default only applies when nothing else is being targeted.
+        if self.bits.is_none() && self.zeros.is_none() && target.is_trivial() {
+            target.bits = self.default_bits.unwrap_or(32);
+        }
+        Ok(target)
+    }
+}
+
+/// Prints the expected effort of meeting the target, measuring the hashrate for a bit first.
+fn estimate<H: PowHasher>(commit: &CommitBuffer, target: &Target, config: &Config) -> Result<()> {
+    const WARMUP: Duration = Duration::from_millis(500);
+    let attempts = target.expected_attempts(H::OUTPUT_BITS);
+    let num_hashes: u64 = measure_hashrate::<H>(commit, config.num_threads(), WARMUP)?
+        .iter()
+        .sum();
+    let hashrate = num_hashes as f64 / WARMUP.as_secs_f64();
+    println!(
+        "Expected attempts: {:.0} (2^{:.1})",
+        attempts,
+        attempts.log2()
+    );
+    if target.regex.is_some() {
+        println!("This doesn't account for the regex, which will take even more attempts");
+    }
+    println!(
+        "Measured hashrate: {:.3}MH/s with {} threads using {}",
+        hashrate / 1_000_000.0,
+        config.num_threads(),
+        H::backend()
+    );
+    println!("Expected time: {}", format_seconds(attempts / hashrate));
+    Ok(())
+}
+
+fn bench<H: PowHasher>(threads: Option<u8>, duration: Duration) -> Result<()> {
+    // A typical commit with a one-line message, with made-up ids of the right length
+    let id = "0".repeat(H::OUTPUT_BITS as usize / 4);
+    let commit = format!(
+        "tree {id}\nparent {id}\n\
+         author A U Thor <author@example.com> 1600000000 +0000\n\
+         committer A U Thor <author@example.com> 1600000000 +0000\n\
+         \nAdd some feature\n",
+        id = id
+    );
+    let commit = CommitBuffer::new(commit.as_bytes(), NonceLocation::Header)?;
+
+    let num_threads = num_threads(threads);
+    println!(
+        "Hashing for {} seconds with {} threads using {}...",
+        duration.as_secs_f64(),
+        num_threads,
+        H::backend()
+    );
+    let counts = measure_hashrate::<H>(&commit, num_threads, duration)?;
+    let to_rate = |count: u64| to_mhps(count as f64, duration);
+    for (i, &count) in counts.iter().enumerate() {
+        println!("Thread {}: {:.3}MH/s", i, to_rate(count));
+    }
+    println!("Total: {:.3}MH/s", to_rate(counts.iter().sum()));
+    Ok(())
+}
+
+/// Measures the hashrate on the commit with a few different numbers of threads, and returns the
+/// one that did best. Running a thread on every hyperthread doesn't always pay off, so this
+/// tries 1, 2, 4, ... threads, as well as one per physical core and one per logical core.
+fn auto_threads<H: PowHasher>(commit: &CommitBuffer, config: &Config) -> Result<usize> {
+    const WARMUP: Duration = Duration::from_millis(300);
+    let max = num_cpus::get();
+    let mut candidates: Vec<_> = std::iter::successors(Some(1), |&n| Some(n * 2))
+        .take_while(|&n| n < max)
+        .chain([num_cpus::get_physical(), max])
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    if config.human_output() {
+        println!("Measuring the hashrate with {:?} threads...", candidates);
+    }
+    let (mut best, mut best_rate) = (1, 0.0);
+    for num_threads in candidates {
+        let num_hashes: u64 = measure_hashrate::<H>(commit, num_threads, WARMUP)
+            .map_err(|_| Error::msg("Interrupted, so the commit was left as it was"))?
+            .iter()
+            .sum();
+        let rate = to_mhps(num_hashes as f64, WARMUP);
+        // Fewer threads win ties, since they leave the rest of the machine alone
+        if rate > best_rate {
+            best = num_threads;
+            best_rate = rate;
+        }
+    }
+    if config.human_output() {
+        println!("Using {} threads ({:.3}MH/s)", best, best_rate);
+    }
+    Ok(best)
+}
+
+/// Mines a commit on the GPU or CPU, whichever was asked for.
+fn mine(
+    buf: CommitBuffer,
+    target: Target,
+    object_format: ObjectFormat,
+    config: &Config,
+) -> Result<Mined> {
+    let terminal;
+    let sink: &dyn ProgressSink = if config.human_output() {
+        terminal =
+            TerminalProgress::new(&target, object_format.bits(), config.target.zeros.is_some());
+        &terminal
+    } else {
+        &NoProgress
+    };
+    let options = config.mine_options();
+    if config.gpu {
+        match object_format {
+            ObjectFormat::Sha1 => run_pow_gpu(buf, target, &options, sink),
+            ObjectFormat::Sha256 => Err(Error::msg("Mining on a GPU is only supported for SHA-1")),
+        }
+    } else {
+        let num_threads = match (config.threads, object_format) {
+            (None, ObjectFormat::Sha1) if config.auto_threads => {
+                auto_threads::<Sha1>(&buf, config)?
             }
-            PowMessage::Done(buf, hash) => {
-                stop.store(true, Ordering::Relaxed);
-                // Print out some statistics once we're done
-                let num_hashes = num_hashes.load(Ordering::Relaxed);
-                let num_seconds =
-                    Instant::now().duration_since(start_time).as_millis() as f64 / 1000.0;
-                println!(
-                    "\n{} attempts / {} seconds = {:.3}MH/s",
-                    num_hashes,
-                    num_seconds,
-                    num_hashes as f64 / 1_000_000.0 / num_seconds as f64
-                );
-                let hash = Oid::from_bytes(&hash)?;
-                return Ok((buf, hash));
+            (None, ObjectFormat::Sha256) if config.auto_threads => {
+                auto_threads::<Sha256>(&buf, config)?
             }
+            _ => config.num_threads(),
+        };
+        let pool = Pool::new(num_threads, config.pin)?;
+        match object_format {
+            ObjectFormat::Sha1 => run_pow::<Sha1>(&pool, buf, target, &options, sink),
+            ObjectFormat::Sha256 => run_pow::<Sha256>(&pool, buf, target, &options, sink),
         }
     }
 }
 
+/// Writes the diff from the `original` commit object to the mined one, if asked to.
+fn write_patch(config: &Config, original: &[u8], original_name: &str, mined: &Mined) -> Result<()> {
+    if let Some(path) = &config.patch {
+        let diff = patch::diff(
+            original,
+            mined.commit.data(),
+            original_name,
+            &to_hex(&mined.hash),
+        );
+        std::fs::write(path, diff)
+            .map_err(|err| Error::msg(format!("Cannot write {}: {}", path.display(), err)))?;
+    }
+    Ok(())
+}
+
+/// Prints the output meant for scripts, with `--stdout`, `--porcelain` or `--format json`. Without
+/// anything `mined`, that's the original commit, which was left as it was.
+fn report(
+    config: &Config,
+    original_id: &[u8],
+    original: &[u8],
+    mined: Option<&Mined>,
+) -> Result<()> {
+    let (id, object) = match mined {
+        Some(mined) => (&mined.hash[..], mined.commit.data()),
+        None => (original_id, original),
+    };
+    if config.stdout {
+        std::io::stdout().write_all(object)?;
+    }
+    if config.porcelain {
+        println!("{}", to_hex(id));
+    }
+    if config.format == OutputFormat::Json {
+        println!("{}", json_report(original_id, mined));
+    }
+    Ok(())
+}
+
+/// The object `--format json` prints. Everything in it is a number, a boolean or hex, so nothing
+/// needs escaping.
+fn json_report(original_id: &[u8], mined: Option<&Mined>) -> String {
+    let (new_id, attempts, elapsed) = match mined {
+        Some(mined) => (&mined.hash[..], mined.attempts, mined.elapsed),
+        None => (original_id, 0, Duration::from_secs(0)),
+    };
+    format!(
+        "{{\"original_oid\":\"{}\",\"new_oid\":\"{}\",\"mined\":{},\"bits\":{},\
+         \"attempts\":{},\"seconds\":{},\"hashrate\":{}}}",
+        to_hex(original_id),
+        to_hex(new_id),
+        mined.is_some(),
+        num_leading_zero_bits(new_id),
+        attempts,
+        elapsed.as_secs_f64(),
+        to_mhps(attempts as f64, elapsed) * 1_000_000.0
+    )
+}
+
+/// Mines a commit object read from stdin, printing the result to stdout instead of going
+/// through a repository at all.
+fn mine_stdin(config: &Config) -> Result<()> {
+    let mut data = Vec::new();
+    std::io::stdin().read_to_end(&mut data)?;
+    let object_format = config.object_format.unwrap_or(ObjectFormat::Sha1);
+    let target = config.target.target(None, None)?;
+    target.validate(object_format.bits())?;
+    // Every hash meets a trivial target, so the object passes through as it is
+    if target.is_trivial() {
+        std::io::stdout().write_all(&data)?;
+        return Ok(());
+    }
+    let buf = CommitBuffer::new(&data, config.nonce_location)?;
+    let mined = mine(buf, target, object_format, config)?;
+    write_patch(config, &data, "stdin", &mined)?;
+    std::io::stdout().write_all(mined.commit.data())?;
+    Ok(())
+}
+
+fn verify(
+    repo: &dyn Repo,
+    object_format: ObjectFormat,
+    revspec: &str,
+    config: &TargetConfig,
+) -> Result<()> {
+    let id = repo.resolve_commit(revspec)?;
+    let target = config.target(Some(repo), Some(&id))?;
+    target.validate(object_format.bits())?;
+
+    let num_zeros = num_leading_zero_bits(&id);
+    let mut progress = vec![format!("{} leading zero bits", num_zeros)];
+    progress.extend(target.progress(&id));
+    println!(
+        "{} has {}",
+        color::hash(&id, color::stdout()),
+        progress.join(", ")
+    );
+    if !target.matches(&id, num_zeros) {
+        return Err(Error::msg(format!(
+            "Commit {} does not meet the target of {}",
+            to_hex(&id),
+            target
+        )));
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
-    let config = Config::from_args();
-    let repo = Repository::open(std::env::current_dir()?)?;
-    let head_commit_hash = repo.head()?.peel_to_commit()?.id();
-    let odb = repo.odb()?;
-    let buf = CommitBuffer::new(odb.read(head_commit_hash)?.data())?;
+    let matches = Config::clap().get_matches();
+    let mut config = Config::from_clap(&matches);
+    config.apply_defaults(config_file::load()?, &matches);
+    config.stdout |= config.stdin;
+    config.dry_run |= config.stdout;
+    logger::init(config.verbose);
+    // From here on, there's always a seed, which is logged so that a run can be repeated
+    let seed = *config.seed.get_or_insert_with(random_seed);
+    info!("Using seed {}", seed);
+    signal::install();
+    if let Some(backend) = config.sha1_backend {
+        backend.select()?;
+    }
+    if let Some(Command::Completions { shell }) = config.command {
+        Config::clap().gen_completions_to("git-power", shell, &mut std::io::stdout());
+        return Ok(());
+    }
+    if let Some(Command::Man) = config.command {
+        print!("{}", man::render());
+        return Ok(());
+    }
+    if let Some(Command::Bench { threads, seconds }) = config.command {
+        return match config.object_format.unwrap_or(ObjectFormat::Sha1) {
+            ObjectFormat::Sha1 => bench::<Sha1>(threads, seconds),
+            ObjectFormat::Sha256 => bench::<Sha256>(threads, seconds),
+        };
+    }
+    if let Some(Command::Hook(HookCommand::Install {
+        bits,
+        pre_push,
+        force,
+    })) = config.command
+    {
+        return hook::install(bits, pre_push, force);
+    }
+    if config.stdin {
+        return mine_stdin(&config);
+    }
+    let (repo, object_format) = repo::open(config.object_format)?;
+    if let Some(Command::Verify { revspec, target }) = &config.command {
+        return verify(&*repo, object_format, revspec, target);
+    }
+
+    let target = config.target.target(Some(&*repo), None)?;
+    target.validate(object_format.bits())?;
+    let original_id = repo.resolve_commit(&config.revspec)?;
+    // Every hash has at least zero leading zeros, so there's nothing to do, and neither is there
+    // for a commit that meets the target already, unless it's the effort that's in question
+    if target.is_trivial()
+        || (!config.estimate && target.matches(&original_id, num_leading_zero_bits(&original_id)))
+    {
+        if config.human_output() {
+            println!(
+                "{} already meets the target, leaving it as it is",
+                color::hash(&original_id, color::stdout())
+            );
+        }
+        return report(
+            &config,
+            &original_id,
+            &repo.read_commit(&original_id)?,
+            None,
+        );
+    }
+    // Changing a commit changes the hashes of everything after it, so mining anything but HEAD
+    // requires the commits in between to be rewritten as well
+    let descendants = if original_id == repo.resolve_commit("HEAD")? {
+        Vec::new()
+    } else {
+        let descendants = repo.descendants(&original_id)?;
+        if descendants.is_empty() {
+            return Err(Error::msg(format!(
+                "{} is not an ancestor of HEAD",
+                config.revspec
+            )));
+        }
+        if !config.rewrite_descendants {
+            return Err(Error::msg(format!(
+                "Mining {} would change the hashes of the {} commits up to HEAD, pass \
+                 --rewrite-descendants to rewrite them on top of it",
+                config.revspec,
+                descendants.len()
+            )));
+        }
+        // Checking this up front saves mining a commit only to fail on what comes after it
+        let mut commits = Vec::new();
+        for id in descendants {
+            let commit = repo.read_commit(&id)?;
+            if is_signed(&commit) {
+                return Err(Error::msg(format!(
+                    "Cannot rewrite {}, since it's signed and its signature wouldn't be valid \
+                     anymore",
+                    to_hex(&id)
+                )));
+            }
+            commits.push((id, commit));
+        }
+        commits
+    };
+    let original = repo.read_commit(&original_id)?;
+    let buf = CommitBuffer::new(&original, config.nonce_location)?;
+    if config.estimate {
+        return match object_format {
+            ObjectFormat::Sha1 => estimate::<Sha1>(&buf, &target, &config),
+            ObjectFormat::Sha256 => estimate::<Sha256>(&buf, &target, &config),
+        };
+    }
 
     // Find the hash we're looking for, then commit the buffer to the git object db,
     // and finally soft reset to point HEAD to the new commit.
-    let (buf, hash) = run_pow(buf, &config)?;
-    odb.write(ObjectType::Commit, buf.data().as_bytes())?;
-    repo.reset(&repo.find_object(hash, None)?, ResetType::Soft, None)?;
-    Ok(())
+    let mined = mine(buf, target, object_format, &config)?;
+    write_patch(&config, &original, &to_hex(&original_id), &mined)?;
+    if config.dry_run {
+        if config.human_output() {
+            println!(
+                "Dry run, so {} with nonce {} wasn't written",
+                color::hash(&mined.hash, color::stdout()),
+                String::from_utf8_lossy(mined.commit.nonce())
+            );
+        }
+    } else {
+        let written = repo.write_commit(mined.commit.data())?;
+        if written != mined.hash {
+            return Err(Error::msg(format!(
+                "Mined commit was written as {}, but it was expected to hash to {}",
+                to_hex(&written),
+                to_hex(&mined.hash)
+            )));
+        }
+        let head = rewrite_descendants(&*repo, &descendants, &original_id, &mined.hash)?;
+        if config.no_reset {
+            if config.human_output() {
+                println!("Wrote {}, leaving HEAD where it was", to_hex(&head));
+            }
+        } else {
+            if !descendants.is_empty() && config.human_output() {
+                println!(
+                    "Rewrote {} commits on top of it, HEAD is now {}",
+                    descendants.len(),
+                    to_hex(&head)
+                );
+            }
+            repo.update_head(&head)?;
+        }
+    }
+
+    report(&config, &original_id, &original, Some(&mined))
 }
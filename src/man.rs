@@ -0,0 +1,124 @@
+use crate::Config;
+use structopt::clap::{AppSettings, ArgSettings};
+use structopt::StructOpt;
+
+// A man page in roff, built from the same definitions as `--help`, so that the options it
+// lists can't fall out of date. Only the description of how git-power goes about its job is
+// written out by hand.
+
+const DESCRIPTION: &str = "\
+.SH DESCRIPTION
+git-power rewrites the commit HEAD points to, or the one given as \\fIREVSPEC\\fR, so that \
+its hash meets a target, which is 32 leading zero bits unless other options say otherwise.
+.PP
+To do so, it adds a nonce to the commit: a \\fBnonce\\fR header following the existing ones, \
+or a \\fBNonce:\\fR trailer at the end of the message with \\fB\\-\\-nonce\\-location \
+trailer\\fR. For signed commits, the nonce goes into the armor headers of the signature \
+instead, which the signature doesn't cover, so it stays valid. The nonce is made up of 32 \
+letters from A to P, each encoding 4 bits, and git-power hashes the commit with one value of \
+it after another, on all CPU cores, until the hash meets the target.
+.PP
+Since nothing before the nonce changes between attempts, the hash state up to it is only \
+computed once, and each attempt only hashes what comes after. Once a nonce is found, the \
+commit is written to the object database, and HEAD is moved to it like a soft reset would, \
+along with the branch it refers to.
+";
+
+/// Renders the man page.
+pub fn render() -> String {
+    let app = Config::clap();
+    let mut page = String::new();
+    page.push_str(&format!(
+        ".TH GIT-POWER 1 \"\" \"git-power {}\"\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    page.push_str(".SH NAME\ngit-power \\- make your git tree into a blockchain\n");
+    page.push_str(
+        ".SH SYNOPSIS\n\\fBgit power\\fR [\\fIOPTIONS\\fR] [\\fIREVSPEC\\fR] [\\fISUBCOMMAND\\fR]\n",
+    );
+    page.push_str(DESCRIPTION);
+
+    // Flags and options are listed together, sorted the same way `--help` does
+    let mut options = Vec::new();
+    for flag in app
+        .p
+        .flags
+        .iter()
+        .filter(|f| !f.b.is_set(ArgSettings::Hidden))
+    {
+        let switch = switch(flag.s.short, flag.s.long);
+        options.push((flag.s.long, switch, flag.b.long_help.or(flag.b.help), None));
+    }
+    for opt in app
+        .p
+        .opts
+        .iter()
+        .filter(|o| !o.b.is_set(ArgSettings::Hidden))
+    {
+        let value = opt
+            .v
+            .val_names
+            .as_ref()
+            .and_then(|names| names.values().next().cloned())
+            .unwrap_or(opt.b.name);
+        let switch = format!(
+            "{} \\fI<{}>\\fR",
+            switch(opt.s.short, opt.s.long),
+            escape(value)
+        );
+        let mut extra = Vec::new();
+        if let Some(default) = opt.v.default_val {
+            extra.push(format!("[default: {}]", default.to_string_lossy()));
+        }
+        if let Some(values) = &opt.v.possible_vals {
+            extra.push(format!("[possible values: {}]", values.join(", ")));
+        }
+        let help = opt.b.long_help.or(opt.b.help);
+        options.push((opt.s.long, switch, help, Some(extra.join(" "))));
+    }
+    options.sort_by_key(|&(long, ..)| long);
+    page.push_str(".SH OPTIONS\n");
+    for (_, switch, help, extra) in options {
+        page.push_str(&format!(".TP\n{}\n", switch));
+        let help = [help.unwrap_or_default(), &extra.unwrap_or_default()]
+            .iter()
+            .filter(|part| !part.is_empty())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ");
+        // An empty line would show up as one
+        if !help.is_empty() {
+            page.push_str(&format!("{}\n", escape(&help)));
+        }
+    }
+
+    page.push_str(".SH SUBCOMMANDS\n");
+    for command in &app.p.subcommands {
+        if command.p.is_set(AppSettings::Hidden) {
+            continue;
+        }
+        page.push_str(&format!(
+            ".TP\n\\fB{}\\fR\n{}\n",
+            command.p.meta.name,
+            escape(command.p.meta.about.unwrap_or_default())
+        ));
+    }
+    page
+}
+
+fn switch(short: Option<char>, long: Option<&str>) -> String {
+    let short = short.map(|short| format!("\\fB\\-{}\\fR", short));
+    let long = long.map(|long| format!("\\fB\\-\\-{}\\fR", escape(long)));
+    short.into_iter().chain(long).collect::<Vec<_>>().join(", ")
+}
+
+/// Escapes text for roff, where backslashes start escapes, hyphens need to be spelled out to
+/// not turn into dashes, and a line starting with a period or quote would be a request.
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
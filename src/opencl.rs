@@ -0,0 +1,379 @@
+use crate::gpu::Gpu;
+use crate::hasher::Sha1Midstate;
+use anyhow::{Error, Result};
+use std::ffi::{c_char, c_void};
+use std::ptr;
+
+// Mining on a GPU through OpenCL. The few OpenCL functions we need are declared by hand and
+// linked against the system's OpenCL library.
+
+const PRELUDE: &str = "\
+#define KERNEL __kernel
+#define GLOBAL __global
+#define GLOBAL_ID() get_global_id(0)
+#define ROTL(x, n) rotate((x), (uint)(n))
+#define CLZ(x) clz(x)
+#define ATOMIC_CMPXCHG(p, cmp, val) atomic_cmpxchg((p), (cmp), (val))
+";
+
+type ClInt = i32;
+type ClUint = u32;
+type Handle = *mut c_void;
+
+const CL_SUCCESS: ClInt = 0;
+const CL_DEVICE_TYPE_GPU: u64 = 1 << 2;
+const CL_DEVICE_TYPE_ALL: u64 = 0xffff_ffff;
+const CL_DEVICE_NAME: ClUint = 0x102b;
+const CL_PROGRAM_BUILD_LOG: ClUint = 0x1183;
+const CL_MEM_READ_WRITE: u64 = 1 << 0;
+const CL_MEM_READ_ONLY: u64 = 1 << 2;
+const CL_MEM_COPY_HOST_PTR: u64 = 1 << 5;
+const CL_TRUE: ClUint = 1;
+
+#[link(name = "OpenCL")]
+extern "C" {
+    fn clGetPlatformIDs(num_entries: ClUint, platforms: *mut Handle, num: *mut ClUint) -> ClInt;
+    fn clGetDeviceIDs(
+        platform: Handle,
+        device_type: u64,
+        num_entries: ClUint,
+        devices: *mut Handle,
+        num: *mut ClUint,
+    ) -> ClInt;
+    fn clGetDeviceInfo(
+        device: Handle,
+        param: ClUint,
+        size: usize,
+        value: *mut c_void,
+        size_ret: *mut usize,
+    ) -> ClInt;
+    fn clCreateContext(
+        properties: *const isize,
+        num_devices: ClUint,
+        devices: *const Handle,
+        notify: *const c_void,
+        user_data: *mut c_void,
+        err: *mut ClInt,
+    ) -> Handle;
+    fn clCreateCommandQueue(context: Handle, device: Handle, props: u64, err: *mut ClInt)
+        -> Handle;
+    fn clCreateProgramWithSource(
+        context: Handle,
+        count: ClUint,
+        strings: *const *const c_char,
+        lengths: *const usize,
+        err: *mut ClInt,
+    ) -> Handle;
+    fn clBuildProgram(
+        program: Handle,
+        num_devices: ClUint,
+        devices: *const Handle,
+        options: *const c_char,
+        notify: *const c_void,
+        user_data: *mut c_void,
+    ) -> ClInt;
+    fn clGetProgramBuildInfo(
+        program: Handle,
+        device: Handle,
+        param: ClUint,
+        size: usize,
+        value: *mut c_void,
+        size_ret: *mut usize,
+    ) -> ClInt;
+    fn clCreateKernel(program: Handle, name: *const c_char, err: *mut ClInt) -> Handle;
+    fn clCreateBuffer(
+        context: Handle,
+        flags: u64,
+        size: usize,
+        host_ptr: *mut c_void,
+        err: *mut ClInt,
+    ) -> Handle;
+    fn clSetKernelArg(kernel: Handle, index: ClUint, size: usize, value: *const c_void) -> ClInt;
+    fn clEnqueueWriteBuffer(
+        queue: Handle,
+        buffer: Handle,
+        blocking: ClUint,
+        offset: usize,
+        size: usize,
+        ptr: *const c_void,
+        num_events: ClUint,
+        events: *const Handle,
+        event: *mut Handle,
+    ) -> ClInt;
+    fn clEnqueueNDRangeKernel(
+        queue: Handle,
+        kernel: Handle,
+        work_dim: ClUint,
+        global_offset: *const usize,
+        global_size: *const usize,
+        local_size: *const usize,
+        num_events: ClUint,
+        events: *const Handle,
+        event: *mut Handle,
+    ) -> ClInt;
+    fn clEnqueueReadBuffer(
+        queue: Handle,
+        buffer: Handle,
+        blocking: ClUint,
+        offset: usize,
+        size: usize,
+        ptr: *mut c_void,
+        num_events: ClUint,
+        events: *const Handle,
+        event: *mut Handle,
+    ) -> ClInt;
+    fn clReleaseMemObject(buffer: Handle) -> ClInt;
+    fn clReleaseKernel(kernel: Handle) -> ClInt;
+    fn clReleaseProgram(program: Handle) -> ClInt;
+    fn clReleaseCommandQueue(queue: Handle) -> ClInt;
+    fn clReleaseContext(context: Handle) -> ClInt;
+}
+
+fn check(err: ClInt, what: &str) -> Result<()> {
+    if err == CL_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::msg(format!("OpenCL error {} while {}", err, what)))
+    }
+}
+
+/// An OpenCL device set up to search for nonces of one particular commit.
+pub struct OpenCl {
+    name: String,
+    context: Handle,
+    queue: Handle,
+    program: Handle,
+    kernel: Handle,
+    buffers: Vec<Handle>,
+    result: Handle,
+}
+
+impl OpenCl {
+    /// Picks the first GPU available, or any other OpenCL device if there is none, and
+    /// uploads the midstate of the commit to it.
+    pub fn new(midstate: &Sha1Midstate, bits: u16) -> Result<Self> {
+        unsafe {
+            let mut platforms = [ptr::null_mut(); 16];
+            let mut num_platforms = 0;
+            check(
+                clGetPlatformIDs(16, platforms.as_mut_ptr(), &mut num_platforms),
+                "listing platforms",
+            )?;
+            let device = platforms[..num_platforms as usize]
+                .iter()
+                .flat_map(|&platform| {
+                    [CL_DEVICE_TYPE_GPU, CL_DEVICE_TYPE_ALL]
+                        .iter()
+                        .map(move |&device_type| (platform, device_type))
+                })
+                .find_map(|(platform, device_type)| {
+                    let mut device = ptr::null_mut();
+                    let mut num_devices = 0;
+                    let err =
+                        clGetDeviceIDs(platform, device_type, 1, &mut device, &mut num_devices);
+                    (err == CL_SUCCESS && num_devices > 0).then_some(device)
+                })
+                .ok_or_else(|| Error::msg("No OpenCL device found"))?;
+
+            let mut name = [0u8; 256];
+            check(
+                clGetDeviceInfo(
+                    device,
+                    CL_DEVICE_NAME,
+                    name.len(),
+                    name.as_mut_ptr() as *mut c_void,
+                    ptr::null_mut(),
+                ),
+                "querying the device name",
+            )?;
+            let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+            let mut err = CL_SUCCESS;
+            let context = clCreateContext(
+                ptr::null(),
+                1,
+                &device,
+                ptr::null(),
+                ptr::null_mut(),
+                &mut err,
+            );
+            check(err, "creating a context")?;
+            // From here on, whatever was created so far is released when `gpu` is dropped
+            let mut gpu = OpenCl {
+                name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+                context,
+                queue: ptr::null_mut(),
+                program: ptr::null_mut(),
+                kernel: ptr::null_mut(),
+                buffers: Vec::new(),
+                result: ptr::null_mut(),
+            };
+            gpu.queue = clCreateCommandQueue(context, device, 0, &mut err);
+            check(err, "creating a command queue")?;
+
+            let sources = [PRELUDE, include_str!("sha1.cl")];
+            let source_ptrs = sources.map(|source| source.as_ptr() as *const c_char);
+            let source_lens = sources.map(str::len);
+            gpu.program = clCreateProgramWithSource(
+                context,
+                2,
+                source_ptrs.as_ptr(),
+                source_lens.as_ptr(),
+                &mut err,
+            );
+            check(err, "creating the program")?;
+            let err = clBuildProgram(
+                gpu.program,
+                1,
+                &device,
+                ptr::null(),
+                ptr::null(),
+                ptr::null_mut(),
+            );
+            if err != CL_SUCCESS {
+                let mut log = vec![0u8; 1 << 16];
+                clGetProgramBuildInfo(
+                    gpu.program,
+                    device,
+                    CL_PROGRAM_BUILD_LOG,
+                    log.len(),
+                    log.as_mut_ptr() as *mut c_void,
+                    ptr::null_mut(),
+                );
+                let log_len = log.iter().position(|&c| c == 0).unwrap_or(log.len());
+                return Err(Error::msg(format!(
+                    "Failed to build the OpenCL kernel:\n{}",
+                    String::from_utf8_lossy(&log[..log_len])
+                )));
+            }
+            let mut err = CL_SUCCESS;
+            gpu.kernel =
+                clCreateKernel(gpu.program, b"search\0".as_ptr() as *const c_char, &mut err);
+            check(err, "creating the kernel")?;
+
+            // The template is hashed as big-endian words, so we convert it once up front
+            let words: Vec<u32> = midstate
+                .template
+                .chunks_exact(4)
+                .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+                .collect();
+            let midstate_buf = gpu.create_buffer(CL_MEM_READ_ONLY, &midstate.h)?;
+            let template_buf = gpu.create_buffer(CL_MEM_READ_ONLY, &words)?;
+            gpu.result = gpu.create_buffer(CL_MEM_READ_WRITE, &[0u32; 2])?;
+            let num_blocks = (words.len() / 16) as u32;
+            let nonce_offset = midstate.nonce_offset as u32;
+            let bits = bits as u32;
+            gpu.set_arg(0, &midstate_buf)?;
+            gpu.set_arg(1, &template_buf)?;
+            gpu.set_arg(2, &num_blocks)?;
+            gpu.set_arg(3, &nonce_offset)?;
+            gpu.set_arg(6, &bits)?;
+            let result = gpu.result;
+            gpu.set_arg(7, &result)?;
+            Ok(gpu)
+        }
+    }
+
+    unsafe fn create_buffer(&mut self, flags: u64, data: &[u32]) -> Result<Handle> {
+        let mut err = CL_SUCCESS;
+        let buffer = clCreateBuffer(
+            self.context,
+            flags | CL_MEM_COPY_HOST_PTR,
+            std::mem::size_of_val(data),
+            data.as_ptr() as *mut c_void,
+            &mut err,
+        );
+        check(err, "creating a buffer")?;
+        self.buffers.push(buffer);
+        Ok(buffer)
+    }
+
+    unsafe fn set_arg<T>(&self, index: ClUint, value: &T) -> Result<()> {
+        check(
+            clSetKernelArg(
+                self.kernel,
+                index,
+                std::mem::size_of::<T>(),
+                value as *const T as *const c_void,
+            ),
+            "setting a kernel argument",
+        )
+    }
+}
+
+impl Gpu for OpenCl {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn search(&mut self, base: u128, count: usize) -> Result<Option<u128>> {
+        unsafe {
+            self.set_arg(4, &(base as u64))?;
+            self.set_arg(5, &((base >> 64) as u64))?;
+            let mut result = [0u32; 2];
+            check(
+                clEnqueueWriteBuffer(
+                    self.queue,
+                    self.result,
+                    CL_TRUE,
+                    0,
+                    std::mem::size_of_val(&result),
+                    result.as_ptr() as *const c_void,
+                    0,
+                    ptr::null(),
+                    ptr::null_mut(),
+                ),
+                "resetting the result",
+            )?;
+            check(
+                clEnqueueNDRangeKernel(
+                    self.queue,
+                    self.kernel,
+                    1,
+                    ptr::null(),
+                    &count,
+                    ptr::null(),
+                    0,
+                    ptr::null(),
+                    ptr::null_mut(),
+                ),
+                "running the kernel",
+            )?;
+            check(
+                clEnqueueReadBuffer(
+                    self.queue,
+                    self.result,
+                    CL_TRUE,
+                    0,
+                    std::mem::size_of_val(&result),
+                    result.as_mut_ptr() as *mut c_void,
+                    0,
+                    ptr::null(),
+                    ptr::null_mut(),
+                ),
+                "reading the result",
+            )?;
+            Ok((result[0] != 0).then(|| base.wrapping_add(result[1] as u128)))
+        }
+    }
+}
+
+impl Drop for OpenCl {
+    fn drop(&mut self) {
+        unsafe {
+            for &buffer in &self.buffers {
+                clReleaseMemObject(buffer);
+            }
+            if !self.kernel.is_null() {
+                clReleaseKernel(self.kernel);
+            }
+            if !self.program.is_null() {
+                clReleaseProgram(self.program);
+            }
+            if !self.queue.is_null() {
+                clReleaseCommandQueue(self.queue);
+            }
+            clReleaseContext(self.context);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// A unified diff between two versions of a commit object, for showing what mining changed
+// about it. Since a nonce only ever gets added or replaced in one place, the objects only differ
+// in a single run of lines, so rather than looking for the smallest diff, the diff consists of
+// one hunk spanning everything between the lines the two versions start and end with.
+
+// Number of unchanged lines shown around the changes
+const CONTEXT: usize = 3;
+
+/// Renders the diff turning `old` into `new`, or nothing if they're the same.
+pub fn diff(old: &[u8], new: &[u8], old_name: &str, new_name: &str) -> Vec<u8> {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take_while(|(old, new)| old == new)
+        .count();
+    // The prefix and suffix can't overlap, or a line would count as unchanged twice
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    if prefix + suffix == old_lines.len() && prefix + suffix == new_lines.len() {
+        return Vec::new();
+    }
+
+    let start = prefix.saturating_sub(CONTEXT);
+    let trailing = suffix.min(CONTEXT);
+    let old_end = old_lines.len() - suffix;
+    let new_end = new_lines.len() - suffix;
+    let mut patch = format!("--- a/{}\n+++ b/{}\n", old_name, new_name).into_bytes();
+    patch.extend_from_slice(
+        format!(
+            "@@ -{} +{} @@\n",
+            range(start, old_end + trailing),
+            range(start, new_end + trailing)
+        )
+        .as_bytes(),
+    );
+    let hunk = (old_lines[start..prefix].iter().map(|line| (b' ', line)))
+        .chain(old_lines[prefix..old_end].iter().map(|line| (b'-', line)))
+        .chain(new_lines[prefix..new_end].iter().map(|line| (b'+', line)))
+        .chain((old_lines[old_end..old_end + trailing].iter()).map(|line| (b' ', line)));
+    for (marker, line) in hunk {
+        patch.push(marker);
+        patch.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            patch.extend_from_slice(b"\n\\ No newline at end of file\n");
+        }
+    }
+    patch
+}
+
+/// Splits text into lines, each keeping its line ending.
+fn lines(text: &[u8]) -> Vec<&[u8]> {
+    text.split_inclusive(|&c| c == b'\n').collect()
+}
+
+/// Formats the lines from `start` to `end` the way hunk headers do, counting from 1. An empty
+/// range is given by the line before it instead.
+fn range(start: usize, end: usize) -> String {
+    if start == end {
+        format!("{},0", start)
+    } else {
+        format!("{},{}", start + 1, end - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff_text(old: &str, new: &str) -> String {
+        String::from_utf8(diff(old.as_bytes(), new.as_bytes(), "old", "new")).unwrap()
+    }
+
+    #[test]
+    fn the_same_object_has_no_diff() {
+        assert_eq!(diff_text("tree t\n\nmsg\n", "tree t\n\nmsg\n"), "");
+        assert_eq!(diff_text("", ""), "");
+    }
+
+    #[test]
+    fn changes_come_with_three_lines_of_context() {
+        let old = "tree t\nparent p\nauthor a\ncommitter c\n\nmsg\n";
+        let new = "tree t\nparent p\nauthor a\ncommitter c\nnonce X\n\nmsg\n";
+        assert_eq!(
+            diff_text(old, new),
+            "--- a/old\n+++ b/new\n@@ -2,5 +2,6 @@\n parent p\n author a\n committer c\n\
+             +nonce X\n \n msg\n"
+        );
+        assert_eq!(
+            diff_text(new, old),
+            "--- a/old\n+++ b/new\n@@ -2,6 +2,5 @@\n parent p\n author a\n committer c\n\
+             -nonce X\n \n msg\n"
+        );
+    }
+
+    #[test]
+    fn a_missing_newline_at_the_end_is_pointed_out() {
+        assert_eq!(
+            diff_text("tree t\nmsg", "tree t\nnonce n\nmsg"),
+            "--- a/old\n+++ b/new\n@@ -1,2 +1,3 @@\n tree t\n+nonce n\n msg\n\
+             \\ No newline at end of file\n"
+        );
+        // Getting a line after it changes the last line too
+        assert_eq!(
+            diff_text("a\nb\nc\nd\nmsg", "a\nb\nc\nd\nmsg\nnonce"),
+            "--- a/old\n+++ b/new\n@@ -2,4 +2,5 @@\n b\n c\n d\n-msg\n\
+             \\ No newline at end of file\n+msg\n+nonce\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn empty_ranges_are_given_by_the_line_before() {
+        assert_eq!(
+            diff_text("", "nonce n\n"),
+            "--- a/old\n+++ b/new\n@@ -0,0 +1,1 @@\n+nonce n\n"
+        );
+        assert_eq!(
+            diff_text("nonce n\n", ""),
+            "--- a/old\n+++ b/new\n@@ -1,1 +0,0 @@\n-nonce n\n"
+        );
+        assert_eq!(range(4, 4), "4,0");
+        assert_eq!(range(4, 6), "5,2");
+    }
+}
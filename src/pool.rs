@@ -0,0 +1,240 @@
+use anyhow::{Error, Result};
+use log::debug;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// A fixed set of worker threads, each of which can be handed jobs to run. Rather than the
+// threads being spawned for a single search and then left to their own devices, they live as
+// long as the pool does, any errors or panics in the jobs are passed back, and the threads are
+// shut down and joined once the pool is dropped.
+
+pub struct Pool {
+    workers: Vec<(Sender<Job>, JoinHandle<()>)>,
+}
+
+impl Pool {
+    /// Starts the worker threads. With `pin`, each of them is pinned to a CPU of its own, as
+    /// far as there are enough CPUs to go around.
+    pub fn new(num_threads: usize, pin: bool) -> Result<Self> {
+        // Without any workers, nothing would ever get mined
+        if num_threads == 0 {
+            return Err(Error::msg("A pool needs at least one thread"));
+        }
+        let cpus = if pin { allowed_cpus()? } else { Vec::new() };
+        let mut pool = Self {
+            workers: Vec::new(),
+        };
+        for i in 0..num_threads {
+            let cpu = cpus.get(i % cpus.len().max(1)).cloned();
+            let (tx, rx) = mpsc::channel::<Job>();
+            let (pinned_tx, pinned_rx) = mpsc::channel();
+            let handle = std::thread::spawn(move || {
+                let _ = pinned_tx.send(cpu.map_or(Ok(()), pin_to));
+                for job in rx {
+                    job();
+                }
+            });
+            // Once the worker is in the pool, it gets cleaned up even if pinning fails
+            pool.workers.push((tx, handle));
+            pinned_rx
+                .recv()
+                .unwrap_or_else(|_| Err(Error::msg("Worker thread exited")))?;
+            if let Some(cpu) = cpu {
+                debug!("Pinned thread {} to CPU {}", i, cpu);
+            }
+        }
+        Ok(pool)
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `f` on every worker at once, passing it the index of the worker.
+    pub fn broadcast<F>(&self, f: F) -> Batch
+    where
+        F: Fn(usize) -> Result<()> + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let (tx, rx) = mpsc::channel();
+        for (i, (jobs, _)) in self.workers.iter().enumerate() {
+            let f = Arc::clone(&f);
+            let tx = tx.clone();
+            let job = Box::new(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| f(i)))
+                    .unwrap_or_else(|_| Err(Error::msg("Worker thread panicked")));
+                // Nobody might be waiting for the result anymore, which is fine
+                let _ = tx.send(result);
+            });
+            jobs.send(job).expect("Worker thread exited");
+        }
+        Batch {
+            results: rx,
+            num_jobs: self.workers.len(),
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        for (jobs, handle) in self.workers.drain(..) {
+            // Hanging up on the worker makes it exit once it's done with its current job
+            drop(jobs);
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The jobs started by `Pool::broadcast`.
+pub struct Batch {
+    results: Receiver<Result<()>>,
+    num_jobs: usize,
+}
+
+impl Batch {
+    /// Waits for every job to finish, returning the first error if any of them failed.
+    pub fn wait(self) -> Result<()> {
+        let mut result = Ok(());
+        for _ in 0..self.num_jobs {
+            let job_result = self
+                .results
+                .recv()
+                .unwrap_or_else(|_| Err(Error::msg("Worker thread exited")));
+            if result.is_ok() {
+                result = job_result;
+            }
+        }
+        result
+    }
+}
+
+/// A value on a cache line of its own. Values shared between the workers should be wrapped in
+/// this, since any of them being written to would otherwise slow down access to whatever else
+/// happens to be on the same line, in every other thread.
+#[repr(align(64))]
+pub struct CachePadded<T>(pub T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// The CPUs the process is allowed to run on.
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> Result<Vec<usize>> {
+    unsafe {
+        let mut set = std::mem::zeroed::<libc::cpu_set_t>();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) != 0 {
+            return Err(Error::msg(format!(
+                "Failed to get the CPUs to pin threads to: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect())
+    }
+}
+
+/// Pins the calling thread to the given CPU.
+#[cfg(target_os = "linux")]
+fn pin_to(cpu: usize) -> Result<()> {
+    unsafe {
+        let mut set = std::mem::zeroed::<libc::cpu_set_t>();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) != 0 {
+            return Err(Error::msg(format!(
+                "Failed to pin a thread to CPU {}: {}",
+                cpu,
+                std::io::Error::last_os_error()
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> Result<Vec<usize>> {
+    Err(Error::msg("Pinning threads is only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to(_: usize) -> Result<()> {
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn runs_on_every_worker() {
+        let pool = Pool::new(4, false).unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&ran);
+        pool.broadcast(move |i| {
+            counter.fetch_add(1 << i, Ordering::Relaxed);
+            Ok(())
+        })
+        .wait()
+        .unwrap();
+        assert_eq!(ran.load(Ordering::Relaxed), 0b1111);
+    }
+
+    #[test]
+    fn a_failing_worker_fails_the_batch() {
+        let pool = Pool::new(3, false).unwrap();
+        let err = pool
+            .broadcast(|i| match i {
+                1 => Err(Error::msg("Worker 1 failed")),
+                _ => Ok(()),
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Worker 1 failed");
+    }
+
+    #[test]
+    fn a_panicking_worker_fails_the_batch() {
+        let pool = Pool::new(3, false).unwrap();
+        let err = pool
+            .broadcast(|i| {
+                assert_ne!(i, 2, "Worker 2 panicked");
+                Ok(())
+            })
+            .wait()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Worker thread panicked");
+        // The worker survives the panic, and the pool can still be used
+        pool.broadcast(|_| Ok(())).wait().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pinned_workers_run_on_a_single_cpu() {
+        let allowed = allowed_cpus().unwrap();
+        let pool = Pool::new(3, true).unwrap();
+        let cpus = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&cpus);
+        pool.broadcast(move |i| {
+            // What this thread may run on, as far as the kernel is concerned
+            sink.lock().unwrap().push((i, allowed_cpus()?));
+            Ok(())
+        })
+        .wait()
+        .unwrap();
+        for (i, cpus) in cpus.lock().unwrap().iter() {
+            // With fewer CPUs than threads, they take turns
+            assert_eq!(cpus, &[allowed[i % allowed.len()]], "thread {}", i);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use git_power::target::{to_base32, Target};
+use git_power::{color, format_seconds, to_mhps, Outcome, ProgressSink, Stats};
+use std::cell::RefCell;
+use std::io::{stderr, IsTerminal, Write};
+use std::time::Instant;
+
+// The progress shown while mining. It goes to stderr, leaving stdout to the results. On a
+// terminal, it's kept to a single line that is written over whenever something changes, while
+// otherwise every new best hash gets a line of its own, so that logs stay readable. Once
+// mining is done, the statistics follow on stdout.
+
+/// The progress of mining towards `target`, as shown on a terminal.
+pub struct TerminalProgress {
+    progress: RefCell<Progress>,
+    target: Target,
+    zeros_as_digits: bool,
+    colored: bool,
+}
+
+impl TerminalProgress {
+    /// Starts showing the progress on a search for `target` among hashes of `hash_bits`. With
+    /// `zeros_as_digits`, leading zeros are counted in hex digits rather than bits.
+    pub fn new(target: &Target, hash_bits: u16, zeros_as_digits: bool) -> Self {
+        // The regex isn't accounted for in the expected number of attempts
+        let expected_attempts =
+            Some(target.expected_attempts(hash_bits)).filter(|_| target.regex.is_none());
+        Self {
+            progress: RefCell::new(Progress::new(expected_attempts)),
+            target: target.clone(),
+            zeros_as_digits,
+            colored: color::stderr(),
+        }
+    }
+}
+
+impl TerminalProgress {
+    /// Describes a new best hash, with `zeros` leading zero bits, and how close it comes.
+    fn describe(&self, hash: &[u8], zeros: u16) -> String {
+        let target = &self.target;
+        let mut parts = Vec::new();
+        if self.zeros_as_digits {
+            parts.push(format!(
+                "{}/{} leading zero digits",
+                zeros / 4,
+                target.bits / 4
+            ));
+        } else if target.bits > 0 {
+            parts.push(format!("{}/{} leading zeros", zeros, target.bits));
+        }
+        parts.extend(target.progress(hash));
+        format!(
+            "Found {} ({})",
+            color::hash(hash, self.colored),
+            parts.join(", ")
+        )
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn on_improvement(&self, hash: &[u8], zeros: u16) {
+        let found = self.describe(hash, zeros);
+        self.progress.borrow_mut().found(found);
+    }
+
+    fn on_done(&self, stats: &Stats) {
+        self.progress.borrow_mut().finish();
+        if stats.outcome == Outcome::Exhausted {
+            return;
+        }
+        println!(
+            "{} attempts / {:.3} seconds = {:.3}MH/s",
+            stats.attempts,
+            stats.elapsed.as_secs_f64(),
+            to_mhps(stats.attempts as f64, stats.elapsed)
+        );
+        match (stats.outcome, stats.hash) {
+            (Outcome::Found, Some(hash)) => {
+                if self.target.base32_prefix.is_some() {
+                    println!("In base32, that's {}", to_base32(hash));
+                }
+                if self.target.prefixes.len() > 1 {
+                    println!(
+                        "Matched prefix {}",
+                        self.target.prefixes.matching(hash).unwrap()
+                    );
+                }
+            }
+            (Outcome::OutOfTime, Some(_)) => {
+                println!("Out of time, using the closest hash found instead")
+            }
+            (Outcome::Interrupted, Some(_)) => {
+                println!("Interrupted, using the closest hash found instead")
+            }
+            _ => {}
+        }
+    }
+
+    fn on_hashrate(&self, mhps: f64) {
+        self.progress.borrow_mut().hashrate(mhps);
+    }
+}
+
+struct Progress {
+    start_time: Instant,
+    expected_attempts: Option<f64>,
+    interactive: bool,
+    found: Option<String>,
+    hashrate: Option<f64>,
+    line_len: usize,
+}
+
+impl Progress {
+    /// Starts reporting progress on a search expected to take `expected_attempts` on
+    /// average, if that's known.
+    fn new(expected_attempts: Option<f64>) -> Self {
+        Self {
+            start_time: Instant::now(),
+            expected_attempts,
+            interactive: stderr().is_terminal(),
+            found: None,
+            hashrate: None,
+            line_len: 0,
+        }
+    }
+
+    /// Shows the best hash so far, as described by `found`.
+    fn found(&mut self, found: String) {
+        self.found = Some(found);
+        self.draw();
+    }
+
+    /// Shows the current hashrate, in MH/s.
+    fn hashrate(&mut self, hashrate: f64) {
+        self.hashrate = Some(hashrate);
+        // Outside of a terminal, this would add a line every time
+        if self.interactive {
+            self.draw();
+        }
+    }
+
+    /// Ends the progress line, so that whatever is printed next starts on a fresh one.
+    fn finish(&mut self) {
+        if self.line_len > 0 {
+            eprintln!();
+            self.line_len = 0;
+        }
+    }
+
+    fn draw(&mut self) {
+        let line = self.line(self.start_time.elapsed().as_secs_f64());
+        let mut stderr = stderr();
+        if self.interactive {
+            let _ = write!(stderr, "{}", self.overwrite(&line));
+        } else {
+            let _ = writeln!(stderr, "{}", line);
+        }
+        let _ = stderr.flush();
+    }
+
+    /// The progress line after `elapsed` seconds of mining.
+    fn line(&self, elapsed: f64) -> String {
+        let mut line = self
+            .found
+            .clone()
+            .unwrap_or_else(|| "Searching".to_string());
+        if let Some(hashrate) = self.hashrate.filter(|&hashrate| hashrate > 0.0) {
+            line.push_str(&format!(
+                " at {:.3}MH/s, {} elapsed",
+                hashrate,
+                format_seconds(elapsed)
+            ));
+            if let Some(attempts) = self.expected_attempts {
+                let expected = attempts / (hashrate * 1_000_000.0);
+                line.push_str(&format!(", ~{} expected", format_seconds(expected)));
+            }
+        }
+        line
+    }
+
+    /// What writes `line` over the one on the terminal.
+    fn overwrite(&mut self, line: &str) -> String {
+        // Whatever is left of a longer previous line needs to be written over
+        let width = color::width(line);
+        let padding = " ".repeat(self.line_len.saturating_sub(width));
+        self.line_len = width;
+        format!("\r{}{}", line, padding)
+    }
+}
+
+impl Drop for Progress {
+    // Mining can also end in an error the sink isn't told about, which shouldn't get printed
+    // at the end of the progress line
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(expected_attempts: Option<f64>) -> Progress {
+        let mut progress = Progress::new(expected_attempts);
+        progress.interactive = false;
+        progress
+    }
+
+    #[test]
+    fn the_line_shows_what_is_known_so_far() {
+        let mut progress = progress(Some(2_000_000.0));
+        assert_eq!(progress.line(1.0), "Searching");
+        // A hashrate of zero is one not measured yet
+        progress.hashrate = Some(0.0);
+        assert_eq!(progress.line(1.0), "Searching");
+        progress.hashrate = Some(0.5);
+        assert_eq!(
+            progress.line(90.0),
+            "Searching at 0.500MH/s, 1.5 minutes elapsed, ~4.000 seconds expected"
+        );
+        progress.found = Some("Found 0000ab".to_string());
+        progress.expected_attempts = None;
+        assert_eq!(
+            progress.line(2.5),
+            "Found 0000ab at 0.500MH/s, 2.500 seconds elapsed"
+        );
+    }
+
+    #[test]
+    fn shorter_lines_blank_out_what_is_left_of_longer_ones() {
+        let mut progress = progress(None);
+        assert_eq!(progress.overwrite("Searching"), "\rSearching");
+        assert_eq!(progress.overwrite("Found"), "\rFound    ");
+        // Color codes take up no room on the terminal
+        let colored = color::hash(&[0x00, 0xab], true);
+        assert_eq!(progress.overwrite(&colored), format!("\r{} ", colored));
+        assert_eq!(progress.line_len, 4);
+    }
+
+    #[test]
+    fn best_hashes_are_described_against_the_target() {
+        let target = Target {
+            bits: 12,
+            trailing_bits: 4,
+            ..Target::default()
+        };
+        let mut progress = TerminalProgress::new(&target, 160, false);
+        progress.colored = false;
+        assert_eq!(
+            progress.describe(&[0x00, 0x1f, 0x30], 11),
+            "Found 001f30 (11/12 leading zeros, 4/4 trailing zeros)"
+        );
+        progress.zeros_as_digits = true;
+        assert_eq!(
+            progress.describe(&[0x00, 0x1f, 0x30], 11),
+            "Found 001f30 (2/3 leading zero digits, 4/4 trailing zeros)"
+        );
+    }
+}
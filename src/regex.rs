@@ -0,0 +1,335 @@
+use anyhow::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+// A small regex engine, for matching against the hex representation of a hash. Rather than
+// backtracking, which can take exponential time on patterns like `(.*)*z`, it works out every
+// position in the input each part of the pattern can end at, given the positions it can start
+// at. Since the input is at most 64 characters long, those fit in the bits of a u128, and this
+// way we get away without pulling in a full regex implementation. The supported syntax is:
+//  - literals, `.`, and escapes like `\.` or `\d`
+//  - character classes, like `[0-9a-f]` or `[^0]`
+//  - the anchors `^` and `$`
+//  - the quantifiers `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`
+//  - groups with `(...)` and alternation with `|`
+// Repetition counts are limited to the length of a hash, and groups to a sane nesting depth,
+// since anything beyond that can't make a difference but could take long to match.
+
+// Upper bound on the counts of `{n,m}`, which is the number of digits in a SHA-256 hash
+const MAX_REPEAT: usize = 64;
+
+// How many groups can be nested into each other
+const MAX_DEPTH: usize = 16;
+
+/// A set of positions in the input, one bit each, from the start up to and including the end.
+type Positions = u128;
+
+#[derive(Clone)]
+pub struct Regex {
+    source: String,
+    root: Vec<Vec<Piece>>,
+}
+
+#[derive(Clone)]
+struct Piece {
+    atom: Atom,
+    min: usize,
+    max: usize,
+}
+
+#[derive(Clone)]
+enum Atom {
+    Byte(u8),
+    Any,
+    Class(Vec<(u8, u8)>, bool),
+    Start,
+    End,
+    Group(Vec<Vec<Piece>>),
+}
+
+impl Regex {
+    /// Whether the pattern matches anywhere in the input, unless anchored. The input can be up
+    /// to 127 bytes long, which is plenty for any hash.
+    pub fn is_match(&self, input: &[u8]) -> bool {
+        assert!(input.len() < Positions::BITS as usize, "input too long");
+        let starts = Positions::MAX >> (Positions::BITS as usize - 1 - input.len());
+        match_alt(&self.root, input, starts) != 0
+    }
+}
+
+impl FromStr for Regex {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser {
+            pattern: s.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let root = parser.parse_alt()?;
+        if parser.pos < parser.pattern.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+        Ok(Self {
+            source: s.to_string(),
+            root,
+        })
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+struct Parser<'a> {
+    pattern: &'a [u8],
+    pos: usize,
+    /// Number of groups the parser is in
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> Error {
+        Error::msg(format!(
+            "Invalid regex {}: {} at offset {}",
+            String::from_utf8_lossy(self.pattern),
+            msg,
+            self.pos
+        ))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Result<u8> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn parse_alt(&mut self) -> Result<Vec<Vec<Piece>>> {
+        let mut alternatives = vec![self.parse_seq()?];
+        while self.peek() == Some(b'|') {
+            self.pos += 1;
+            alternatives.push(self.parse_seq()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_seq(&mut self) -> Result<Vec<Piece>> {
+        let mut pieces = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == b'|' || c == b')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            let (min, max) = self.parse_quantifier()?;
+            pieces.push(Piece { atom, min, max });
+        }
+        Ok(pieces)
+    }
+
+    fn parse_atom(&mut self) -> Result<Atom> {
+        Ok(match self.next()? {
+            b'.' => Atom::Any,
+            b'^' => Atom::Start,
+            b'$' => Atom::End,
+            b'(' => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error(&format!("groups nested more than {} deep", MAX_DEPTH)));
+                }
+                self.depth += 1;
+                let group = self.parse_alt()?;
+                self.depth -= 1;
+                if self.next()? != b')' {
+                    return Err(self.error("expected ')'"));
+                }
+                Atom::Group(group)
+            }
+            b'[' => {
+                let negated = self.peek() == Some(b'^');
+                if negated {
+                    self.pos += 1;
+                }
+                let mut ranges = Vec::new();
+                loop {
+                    let start = match self.next()? {
+                        b']' if !ranges.is_empty() => break,
+                        b'\\' => self.next()?,
+                        c => c,
+                    };
+                    if self.peek() == Some(b'-') && self.pattern.get(self.pos + 1) != Some(&b']') {
+                        self.pos += 1;
+                        let end = self.next()?;
+                        if end < start {
+                            return Err(self.error("invalid range"));
+                        }
+                        ranges.push((start, end));
+                    } else {
+                        ranges.push((start, start));
+                    }
+                }
+                Atom::Class(ranges, negated)
+            }
+            b'\\' => match self.next()? {
+                b'd' => Atom::Class(vec![(b'0', b'9')], false),
+                c => Atom::Byte(c),
+            },
+            b'*' | b'+' | b'?' | b'{' => return Err(self.error("nothing to repeat")),
+            c => Atom::Byte(c),
+        })
+    }
+
+    fn parse_quantifier(&mut self) -> Result<(usize, usize)> {
+        let quantifier = match self.peek() {
+            Some(b'*') => (0, usize::MAX),
+            Some(b'+') => (1, usize::MAX),
+            Some(b'?') => (0, 1),
+            Some(b'{') => {
+                self.pos += 1;
+                let min = self.parse_number()?;
+                if min > MAX_REPEAT {
+                    return Err(self.error(&format!(
+                        "repetition count above {}, the length of a hash",
+                        MAX_REPEAT
+                    )));
+                }
+                let max = match self.next()? {
+                    b'}' => return Ok((min, min)),
+                    b',' if self.peek() == Some(b'}') => usize::MAX,
+                    b',' => self.parse_number()?,
+                    _ => return Err(self.error("expected ',' or '}'")),
+                };
+                if self.peek() != Some(b'}') || max < min {
+                    return Err(self.error("invalid repetition"));
+                }
+                if max > MAX_REPEAT && max != usize::MAX {
+                    return Err(self.error(&format!(
+                        "repetition count above {}, the length of a hash",
+                        MAX_REPEAT
+                    )));
+                }
+                (min, max)
+            }
+            _ => return Ok((1, 1)),
+        };
+        self.pos += 1;
+        Ok(quantifier)
+    }
+
+    fn parse_number(&mut self) -> Result<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.pattern[start..self.pos])
+            .unwrap()
+            .parse()
+            .map_err(|_| self.error("expected a number"))
+    }
+}
+
+// Each of these takes the positions a part of the pattern can start at, and gives the
+// positions it can end at. Nothing matches wherever that comes up empty.
+
+fn match_alt(alternatives: &[Vec<Piece>], input: &[u8], starts: Positions) -> Positions {
+    alternatives
+        .iter()
+        .fold(0, |ends, seq| ends | match_seq(seq, input, starts))
+}
+
+fn match_seq(seq: &[Piece], input: &[u8], starts: Positions) -> Positions {
+    seq.iter()
+        .fold(starts, |starts, piece| match_piece(piece, input, starts))
+}
+
+fn match_piece(piece: &Piece, input: &[u8], starts: Positions) -> Positions {
+    let mut current = starts;
+    for _ in 0..piece.min {
+        current = match_atom(&piece.atom, input, current);
+    }
+    // Past the minimum, every repetition adds the positions it gets to. Once one doesn't get
+    // anywhere new, neither will any further ones, which makes even unbounded repetitions end.
+    let mut ends = current;
+    for _ in piece.min..piece.max {
+        current = match_atom(&piece.atom, input, current);
+        if current & !ends == 0 {
+            break;
+        }
+        ends |= current;
+    }
+    ends
+}
+
+fn match_atom(atom: &Atom, input: &[u8], starts: Positions) -> Positions {
+    // Moves on by one character from every start where `matches` accepts it
+    let step = |matches: &dyn Fn(u8) -> bool| {
+        let mut ends = 0;
+        for (pos, &c) in input.iter().enumerate() {
+            if starts & (1 << pos) != 0 && matches(c) {
+                ends |= 1 << (pos + 1);
+            }
+        }
+        ends
+    };
+    match atom {
+        Atom::Byte(byte) => step(&|c| c == *byte),
+        Atom::Any => step(&|_| true),
+        Atom::Class(ranges, negated) => {
+            step(&|c| ranges.iter().any(|&(start, end)| start <= c && c <= end) != *negated)
+        }
+        Atom::Start => starts & 1,
+        Atom::End => starts & (1 << input.len()),
+        Atom::Group(alternatives) => match_alt(alternatives, input, starts),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, input: &str) -> bool {
+        pattern.parse::<Regex>().unwrap().is_match(input.as_bytes())
+    }
+
+    #[test]
+    fn matches_like_a_regex() {
+        assert!(is_match("cafe", "00cafe00"));
+        assert!(!is_match("^cafe", "00cafe00"));
+        assert!(is_match("^00", "00cafe00"));
+        assert!(is_match("00$", "00cafe00"));
+        assert!(!is_match("^cafe$", "00cafe00"));
+        assert!(is_match("^0+c[a-f]fe", "00cafe00"));
+        assert!(is_match("^[^1-9]{2}", "00cafe00"));
+        assert!(!is_match("^[^0]", "00cafe00"));
+        assert!(is_match("(beef|cafe)0{2}$", "00cafe00"));
+        assert!(!is_match("(beef|cafe)0{3}$", "00cafe00"));
+        assert!(is_match("^0{1,}ca?fe", "00cafe00"));
+        assert!(is_match("^(0?)*c", "00cafe00"));
+        assert!(is_match("\\d\\d$", "00cafe00"));
+        assert!(is_match("", ""));
+        assert!(is_match("^$", ""));
+        assert!(!is_match("^.$", ""));
+    }
+
+    #[test]
+    fn nested_repetitions_match_quickly() {
+        let hash = "0".repeat(64);
+        assert!(!is_match("(.*)*z", &hash));
+        assert!(!is_match("^((0*)*)*1", &hash));
+        assert!(is_match("^(0|00)*$", &hash));
+    }
+
+    #[test]
+    fn rejects_patterns_too_large_to_matter() {
+        assert!("(^){1000000}".parse::<Regex>().is_err());
+        assert!("0{65}".parse::<Regex>().is_err());
+        assert!("0{1,65}".parse::<Regex>().is_err());
+        assert!("0{64}".parse::<Regex>().is_ok());
+        assert!("0{1,}".parse::<Regex>().is_ok());
+        assert!("(".repeat(100_000).parse::<Regex>().is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::ObjectFormat;
+use anyhow::{Error, Result};
+#[cfg(feature = "git2")]
+use git2::{ObjectType, Oid, Repository, ResetType, Sort};
+use log::warn;
+use std::io::Write;
+#[cfg(feature = "git2")]
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The handful of operations on a repository that mining a commit requires. Object ids are
+/// passed around as raw bytes, since their length depends on the repository's hash.
+pub trait Repo {
+    /// Reads the raw contents of a commit.
+    fn read_commit(&self, id: &[u8]) -> Result<Vec<u8>>;
+
+    /// Resolves a revision, like `HEAD~2` or a branch name, to the id of a commit.
+    fn resolve_commit(&self, revspec: &str) -> Result<Vec<u8>>;
+
+    /// Lists the commits that are both descendants of the given one and ancestors of HEAD,
+    /// HEAD included, with parents coming before their children.
+    fn descendants(&self, id: &[u8]) -> Result<Vec<Vec<u8>>>;
+
+    /// Lists the ids of every object in the object database.
+    fn object_ids(&self) -> Result<Vec<Vec<u8>>>;
+
+    /// Writes a commit object to the object database, returning its id.
+    fn write_commit(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Points HEAD at the given commit, along with the branch it refers to, if any.
+    fn update_head(&self, id: &[u8]) -> Result<()>;
+}
+
+/// Opens the repository at hand, picking the backend that can handle its object format. If
+/// the format isn't given, it's detected from the repository itself.
+#[cfg(feature = "git2")]
+pub fn open(object_format: Option<ObjectFormat>) -> Result<(Box<dyn Repo>, ObjectFormat)> {
+    match object_format {
+        Some(ObjectFormat::Sha1) => Ok((Box::new(Git2Repo::open()?), ObjectFormat::Sha1)),
+        Some(ObjectFormat::Sha256) => Ok((Box::new(GitCli), ObjectFormat::Sha256)),
+        // libgit2 refuses to open repositories with extensions it doesn't know about, which
+        // includes the SHA-256 object format, so anything it opens is a SHA-1 repository.
+        None => match Git2Repo::open() {
+            Ok(repo) => Ok((Box::new(repo), ObjectFormat::Sha1)),
+            Err(err) => match GitCli.object_format() {
+                Ok(ObjectFormat::Sha256) => Ok((Box::new(GitCli), ObjectFormat::Sha256)),
+                _ => Err(err),
+            },
+        },
+    }
+}
+
+/// Without libgit2, everything goes through the `git` binary.
+#[cfg(not(feature = "git2"))]
+pub fn open(object_format: Option<ObjectFormat>) -> Result<(Box<dyn Repo>, ObjectFormat)> {
+    let object_format = match object_format {
+        Some(object_format) => object_format,
+        None => GitCli.object_format()?,
+    };
+    Ok((Box::new(GitCli), object_format))
+}
+
+#[cfg(feature = "git2")]
+pub struct Git2Repo(Repository);
+
+#[cfg(feature = "git2")]
+impl Git2Repo {
+    pub fn open() -> Result<Self> {
+        // Unless `GIT_DIR` says otherwise, this searches upwards from the current directory
+        // for the enclosing repository, so the tool can be invoked from any subdirectory.
+        let work_tree = match std::env::var_os("GIT_WORK_TREE") {
+            Some(work_tree) => work_tree,
+            None => return Ok(Self(Repository::open_from_env()?)),
+        };
+        // libgit2 refuses to open a repository with `GIT_WORK_TREE` set, so the repository
+        // is opened by hand instead, and given the working tree afterwards
+        let repo = match std::env::var_os("GIT_DIR") {
+            Some(git_dir) => Repository::open(git_dir)?,
+            None => Repository::discover(".")?,
+        };
+        repo.set_workdir(Path::new(&work_tree), false)?;
+        Ok(Self(repo))
+    }
+}
+
+#[cfg(feature = "git2")]
+impl Repo for Git2Repo {
+    fn read_commit(&self, id: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.0.odb()?.read(Oid::from_bytes(id)?)?.data().to_vec())
+    }
+
+    fn resolve_commit(&self, revspec: &str) -> Result<Vec<u8>> {
+        let commit = self.0.revparse_single(revspec)?.peel_to_commit()?;
+        Ok(commit.id().as_bytes().to_vec())
+    }
+
+    fn descendants(&self, id: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let ancestor = Oid::from_bytes(id)?;
+        let mut walk = self.0.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walk.push_head()?;
+        walk.hide(ancestor)?;
+        // Anything merged in along the way is reachable from HEAD as well, but doesn't descend
+        // from the commit, so it's left out like `--ancestry-path` would
+        let mut ids = Vec::new();
+        for oid in walk {
+            let oid = oid?;
+            if self.0.graph_descendant_of(oid, ancestor)? {
+                ids.push(oid.as_bytes().to_vec());
+            }
+        }
+        Ok(ids)
+    }
+
+    fn object_ids(&self) -> Result<Vec<Vec<u8>>> {
+        let mut ids = Vec::new();
+        self.0.odb()?.foreach(|oid| {
+            ids.push(oid.as_bytes().to_vec());
+            true
+        })?;
+        Ok(ids)
+    }
+
+    fn write_commit(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let oid = self.0.odb()?.write(ObjectType::Commit, data)?;
+        Ok(oid.as_bytes().to_vec())
+    }
+
+    fn update_head(&self, id: &[u8]) -> Result<()> {
+        let repo = &self.0;
+        let hash = Oid::from_bytes(id)?;
+        if repo.head_detached()? {
+            // There's no branch to move along, so only HEAD itself gets updated
+            repo.set_head_detached(hash)?;
+            warn!("HEAD is detached, so no branch was updated");
+        } else if repo.is_bare() {
+            // Without a working directory there's nothing for a reset to keep in sync, so we
+            // just point the reference behind HEAD at the new commit.
+            repo.head()?
+                .set_target(hash, &format!("power: moving to {}", hash))?;
+        } else {
+            repo.reset(&repo.find_object(hash, None)?, ResetType::Soft, None)?;
+        }
+        Ok(())
+    }
+}
+
+/// Goes through the `git` binary instead of libgit2, which doesn't understand repositories
+/// using the SHA-256 object format, or isn't there at all when built without the `git2`
+/// feature. Since git is run in the current directory, it finds the
+/// repository (and honors `GIT_DIR` and friends) exactly like it would for the user.
+pub struct GitCli;
+
+impl GitCli {
+    fn git(&self, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>> {
+        let mut child = Command::new("git")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(input) = input {
+            child.stdin.take().unwrap().write_all(input)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::msg(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+
+    fn git_id(&self, args: &[&str], input: Option<&[u8]>) -> Result<String> {
+        let output = self.git(args, input)?;
+        Ok(String::from_utf8_lossy(&output).trim().to_string())
+    }
+
+    /// Asks git for the object format, which goes by the repository's
+    /// `extensions.objectFormat` setting, with SHA-1 for repositories without one.
+    fn object_format(&self) -> Result<ObjectFormat> {
+        self.git_id(&["rev-parse", "--show-object-format"], None)?
+            .parse()
+    }
+}
+
+impl Repo for GitCli {
+    fn read_commit(&self, id: &[u8]) -> Result<Vec<u8>> {
+        self.git(&["cat-file", "commit", &to_hex(id)], None)
+    }
+
+    fn resolve_commit(&self, revspec: &str) -> Result<Vec<u8>> {
+        let spec = format!("{}^{{commit}}", revspec);
+        from_hex(&self.git_id(&["rev-parse", "--verify", &spec], None)?)
+    }
+
+    fn descendants(&self, id: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let range = format!("{}..HEAD", to_hex(id));
+        let output = self.git(
+            &[
+                "rev-list",
+                "--ancestry-path",
+                "--topo-order",
+                "--reverse",
+                &range,
+            ],
+            None,
+        )?;
+        String::from_utf8_lossy(&output)
+            .lines()
+            .map(from_hex)
+            .collect()
+    }
+
+    fn object_ids(&self) -> Result<Vec<Vec<u8>>> {
+        let output = self.git(
+            &[
+                "cat-file",
+                "--batch-all-objects",
+                "--batch-check=%(objectname)",
+            ],
+            None,
+        )?;
+        String::from_utf8_lossy(&output)
+            .lines()
+            .map(from_hex)
+            .collect()
+    }
+
+    fn write_commit(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let id = self.git_id(
+            &["hash-object", "-t", "commit", "-w", "--stdin"],
+            Some(data),
+        )?;
+        from_hex(&id)
+    }
+
+    fn update_head(&self, id: &[u8]) -> Result<()> {
+        // Updating HEAD through `update-ref` moves the branch it refers to, which amounts to
+        // a soft reset, and works the same for bare repositories and a detached HEAD.
+        let hash = to_hex(id);
+        let detached = self.git(&["symbolic-ref", "-q", "HEAD"], None).is_err();
+        let message = format!("power: moving to {}", hash);
+        self.git(&["update-ref", "-m", &message, "HEAD", &hash], None)?;
+        if detached {
+            warn!("HEAD is detached, so no branch was updated");
+        }
+        Ok(())
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    // Checking the digits up front also makes sure the string is ASCII, so that slicing it into
+    // pairs of bytes never splits a character
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::msg(format!("Invalid object id: {}", hex)));
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = from_hex("00ff10ab").unwrap();
+        assert_eq!(bytes, [0x00, 0xff, 0x10, 0xab]);
+        assert_eq!(to_hex(&bytes), "00ff10ab");
+    }
+
+    #[test]
+    fn invalid_hex_is_an_error() {
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        // Two bytes, but a single character, which can't be sliced in half
+        assert!(from_hex("é").is_err());
+        assert!(from_hex("0é0").is_err());
+        // u8::from_str_radix would take the sign
+        assert!(from_hex("+1").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+// Searches a range of nonces starting at `base`, one per work item, for one giving a hash with
+// at least `bits` leading zero bits. Hashing starts from `midstate`, the SHA-1 state after
+// every whole block preceding the nonce, and goes through the already padded `tail` of the
+// remaining blocks, as big-endian words, with the nonce written to it at `nonce_offset`.
+// The first work item to find a hash sets `result[0]` and stores its id in `result[1]`.
+//
+// This is compiled both as OpenCL and as CUDA, so anything that differs between the two is
+// left to macros defined by a prelude in each backend.
+
+#define NONCE_LENGTH 32
+
+KERNEL void search(
+    GLOBAL const uint *midstate,
+    GLOBAL const uint *tail,
+    const uint num_blocks,
+    const uint nonce_offset,
+    const ulong base_lo,
+    const ulong base_hi,
+    const uint bits,
+    GLOBAL uint *result)
+{
+    const uint id = GLOBAL_ID();
+    const ulong lo = base_lo + id;
+    const ulong hi = base_hi + (lo < base_lo);
+
+    uint h[5];
+    for (uint i = 0; i < 5; i++) {
+        h[i] = midstate[i];
+    }
+    for (uint block = 0; block < num_blocks; block++) {
+        uint w[16];
+        for (uint i = 0; i < 16; i++) {
+            uint word = tail[16 * block + i];
+            for (uint byte = 0; byte < 4; byte++) {
+                // Same encoding as `encode_nonce`, with the least significant digit last
+                const int pos = (int)(64 * block + 4 * i + byte) - (int)nonce_offset;
+                if (pos >= 0 && pos < NONCE_LENGTH) {
+                    const uint digit = NONCE_LENGTH - 1 - pos;
+                    const ulong half = digit < 16 ? lo : hi;
+                    const uint c = 'A' + (uint)((half >> (4 * (digit % 16))) & 0xf);
+                    const uint shift = 24 - 8 * byte;
+                    word = (word & ~(0xffu << shift)) | (c << shift);
+                }
+            }
+            w[i] = word;
+        }
+
+        uint a = h[0], b = h[1], c = h[2], d = h[3], e = h[4];
+        for (uint t = 0; t < 80; t++) {
+            if (t >= 16) {
+                const uint x = w[(t - 3) % 16] ^ w[(t - 8) % 16] ^ w[(t - 14) % 16] ^ w[t % 16];
+                w[t % 16] = ROTL(x, 1);
+            }
+            uint f, k;
+            if (t < 20) {
+                f = (b & c) | (~b & d);
+                k = 0x5a827999;
+            } else if (t < 40) {
+                f = b ^ c ^ d;
+                k = 0x6ed9eba1;
+            } else if (t < 60) {
+                f = (b & c) | (b & d) | (c & d);
+                k = 0x8f1bbcdc;
+            } else {
+                f = b ^ c ^ d;
+                k = 0xca62c1d6;
+            }
+            const uint temp = ROTL(a, 5) + f + e + k + w[t % 16];
+            e = d;
+            d = c;
+            c = ROTL(b, 30);
+            b = a;
+            a = temp;
+        }
+        h[0] += a;
+        h[1] += b;
+        h[2] += c;
+        h[3] += d;
+        h[4] += e;
+    }
+
+    uint zeros = 0;
+    for (uint i = 0; i < 5; i++) {
+        if (h[i] != 0) {
+            zeros += CLZ(h[i]);
+            break;
+        }
+        zeros += 32;
+    }
+    if (zeros >= bits && ATOMIC_CMPXCHG(&result[0], 0, 1) == 0) {
+        result[1] = id;
+    }
+}
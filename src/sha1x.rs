@@ -0,0 +1,181 @@
+// SHA-1 compression functions written by hand: a portable one, for when nothing faster is
+// available, and ones hashing several messages at once using SIMD. For the latter, each
+// register holds the same word of every lane, so every instruction advances all of the hashes,
+// which gets a lot more work done per cycle than the scalar implementation on CPUs without SHA
+// extensions.
+
+const K: [u32; 4] = [0x5a827999, 0x6ed9eba1, 0x8f1bbcdc, 0xca62c1d6];
+
+/// Runs the SHA-1 compression function on a single block, without any SIMD.
+pub fn compress(state: &mut [u32; 5], block: &[u8; 64]) {
+    let mut w = [0u32; 80];
+    for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for t in 16..80 {
+        w[t] = (w[t - 3] ^ w[t - 8] ^ w[t - 14] ^ w[t - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (t, &word) in w.iter().enumerate() {
+        let f = match t / 20 {
+            0 => (b & c) | (!b & d),
+            2 => (b & c) | (b & d) | (c & d),
+            _ => b ^ c ^ d,
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(K[t / 20])
+            .wrapping_add(word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+// The SIMD implementations only differ in the width of their registers, so they're generated
+// from the same code with the intrinsics swapped out.
+macro_rules! lanes {
+    (
+        $name:ident, $feature:tt, $lanes:literal, $reg:ty,
+        $zero:ident, $set1:ident, $add:ident, $xor:ident, $or:ident, $and:ident, $andnot:ident,
+        $slli:ident, $srli:ident, $loadu:ident, $storeu:ident
+    ) => {
+        #[cfg(target_arch = "x86_64")]
+        pub mod $name {
+            use super::K;
+            use core::arch::x86_64::*;
+
+            pub const LANES: usize = $lanes;
+
+            pub fn is_supported() -> bool {
+                is_x86_feature_detected!($feature)
+            }
+
+            /// Runs the SHA-1 compression function on one block of each lane.
+            ///
+            /// # Safety
+            ///
+            /// The CPU needs to support the instructions used, as checked by `is_supported`.
+            #[target_feature(enable = $feature)]
+            pub unsafe fn compress(states: &mut [[u32; 5]; LANES], blocks: [&[u8; 64]; LANES]) {
+                let mut w = [$zero(); 16];
+                for (i, word) in w.iter_mut().enumerate() {
+                    let mut words = [0u32; LANES];
+                    for (lane, block) in blocks.iter().enumerate() {
+                        let bytes = &block[4 * i..4 * i + 4];
+                        words[lane] = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    }
+                    *word = $loadu(words.as_ptr() as *const $reg);
+                }
+                let mut initial = [$zero(); 5];
+                for (i, word) in initial.iter_mut().enumerate() {
+                    let words: [u32; LANES] = std::array::from_fn(|lane| states[lane][i]);
+                    *word = $loadu(words.as_ptr() as *const $reg);
+                }
+
+                // Each group of 20 rounds uses its own function to mix the state
+                let mut s = initial;
+                for t in 0..20 {
+                    let [_, b, c, d, _] = s;
+                    let f = $or($and(b, c), $andnot(b, d));
+                    round(&mut s, f, K[0], schedule(&mut w, t));
+                }
+                for t in 20..40 {
+                    let [_, b, c, d, _] = s;
+                    let f = $xor($xor(b, c), d);
+                    round(&mut s, f, K[1], schedule(&mut w, t));
+                }
+                for t in 40..60 {
+                    let [_, b, c, d, _] = s;
+                    let f = $or($and(b, c), $and(d, $or(b, c)));
+                    round(&mut s, f, K[2], schedule(&mut w, t));
+                }
+                for t in 60..80 {
+                    let [_, b, c, d, _] = s;
+                    let f = $xor($xor(b, c), d);
+                    round(&mut s, f, K[3], schedule(&mut w, t));
+                }
+
+                for (i, (&start, end)) in initial.iter().zip(s.iter()).enumerate() {
+                    let mut words = [0u32; LANES];
+                    $storeu(words.as_mut_ptr() as *mut $reg, $add(start, *end));
+                    for (state, &word) in states.iter_mut().zip(words.iter()) {
+                        state[i] = word;
+                    }
+                }
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            fn rotl<const L: i32, const R: i32>(x: $reg) -> $reg {
+                $or($slli::<L>(x), $srli::<R>(x))
+            }
+
+            /// Gets word `t` of the message schedule. It only ever looks 16 words back, so
+            /// it's kept in a ring buffer, initially holding the block itself.
+            #[target_feature(enable = $feature)]
+            #[inline]
+            fn schedule(w: &mut [$reg; 16], t: usize) -> $reg {
+                if t >= 16 {
+                    let x = $xor(
+                        $xor(w[(t - 3) % 16], w[(t - 8) % 16]),
+                        $xor(w[(t - 14) % 16], w[t % 16]),
+                    );
+                    w[t % 16] = rotl::<1, 31>(x);
+                }
+                w[t % 16]
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            fn round(s: &mut [$reg; 5], f: $reg, k: u32, w: $reg) {
+                let [a, b, c, d, e] = *s;
+                let temp = $add($add(rotl::<5, 27>(a), f), $add($add(e, $set1(k as i32)), w));
+                *s = [temp, a, rotl::<30, 2>(b), c, d];
+            }
+        }
+    };
+}
+
+lanes!(
+    sse2,
+    "sse2",
+    4,
+    __m128i,
+    _mm_setzero_si128,
+    _mm_set1_epi32,
+    _mm_add_epi32,
+    _mm_xor_si128,
+    _mm_or_si128,
+    _mm_and_si128,
+    _mm_andnot_si128,
+    _mm_slli_epi32,
+    _mm_srli_epi32,
+    _mm_loadu_si128,
+    _mm_storeu_si128
+);
+
+lanes!(
+    avx2,
+    "avx2",
+    8,
+    __m256i,
+    _mm256_setzero_si256,
+    _mm256_set1_epi32,
+    _mm256_add_epi32,
+    _mm256_xor_si256,
+    _mm256_or_si256,
+    _mm256_and_si256,
+    _mm256_andnot_si256,
+    _mm256_slli_epi32,
+    _mm256_srli_epi32,
+    _mm256_loadu_si256,
+    _mm256_storeu_si256
+);
@@ -0,0 +1,32 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Catching Ctrl-C, so that mining can be wound down properly instead of the process being
+// killed at some arbitrary point, possibly after the new commit was written but before HEAD
+// was moved to it. The handler only sets a flag, which the main thread checks whenever it
+// wakes up.
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Starts catching Ctrl-C. Once that's pressed, `interrupted` returns true from then on.
+#[cfg(unix)]
+pub fn install() {
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Elsewhere, Ctrl-C keeps ending the process right away.
+#[cfg(not(unix))]
+pub fn install() {}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
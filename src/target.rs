@@ -0,0 +1,1219 @@
+use crate::num_trailing_zero_bits;
+use crate::regex::Regex;
+use anyhow::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// The conditions a mined hash has to satisfy. All of them have to hold at once, and the
+/// default one is met by every hash.
+#[derive(Clone, Default)]
+pub struct Target {
+    pub bits: u16,
+    pub trailing_bits: u16,
+    pub repeat: usize,
+    pub prefixes: PrefixSet,
+    pub suffix: Option<HexPattern>,
+    pub base32_prefix: Option<Base32Pattern>,
+    pub contains: Option<HexPattern>,
+    pub mask: Option<Mask>,
+    pub bytes: Vec<BytePin>,
+    pub less_than: Option<HexPattern>,
+    pub unique_abbrev: Option<UniqueAbbrev>,
+    pub regex: Option<Regex>,
+}
+
+impl Target {
+    /// Whether every hash satisfies the target, in which case there's nothing to mine.
+    pub fn is_trivial(&self) -> bool {
+        self.bits == 0
+            && self.trailing_bits == 0
+            && self.repeat <= 1
+            && self.prefixes.is_empty()
+            && self.suffix.is_none()
+            && self.base32_prefix.is_none()
+            && self.contains.is_none()
+            && self.mask.is_none()
+            && self.bytes.is_empty()
+            && self.less_than.is_none()
+            && self.unique_abbrev.is_none()
+            && self.regex.is_none()
+    }
+
+    /// Checks that the target can be met at all by a hash of the given width.
+    pub fn validate(&self, hash_bits: u16) -> Result<()> {
+        if self.bits > hash_bits {
+            return Err(Error::msg(format!(
+                "Cannot find {} leading zero bits in a {}-bit hash",
+                self.bits, hash_bits
+            )));
+        }
+        if self.bits as u32 + self.trailing_bits as u32 > hash_bits as u32 {
+            return Err(Error::msg(format!(
+                "Cannot find {} leading and {} trailing zero bits in a {}-bit hash",
+                self.bits, self.trailing_bits, hash_bits
+            )));
+        }
+        if self.repeat > hash_bits as usize / 4 {
+            return Err(Error::msg(format!(
+                "Cannot repeat a digit {} times in a {}-bit hash",
+                self.repeat, hash_bits
+            )));
+        }
+        for pattern in self
+            .prefixes
+            .iter()
+            .chain(&self.suffix)
+            .chain(&self.contains)
+        {
+            if pattern.len() > hash_bits as usize / 4 {
+                return Err(Error::msg(format!(
+                    "Pattern {} is longer than a {}-bit hash",
+                    pattern, hash_bits
+                )));
+            }
+        }
+        if let Some(prefix) = &self.base32_prefix {
+            if 5 * prefix.0.len() > hash_bits as usize {
+                return Err(Error::msg(format!(
+                    "Pattern {} is longer than a {}-bit hash",
+                    prefix, hash_bits
+                )));
+            }
+        }
+        if let Some(mask) = &self.mask {
+            if mask.value.len() != mask.mask.len() {
+                return Err(Error::msg(format!(
+                    "Mask {} and value {} have different lengths",
+                    mask.mask, mask.value
+                )));
+            }
+            if mask.mask.len() > hash_bits as usize / 4 {
+                return Err(Error::msg(format!(
+                    "Mask {} is longer than a {}-bit hash",
+                    mask.mask, hash_bits
+                )));
+            }
+        }
+        for pin in &self.bytes {
+            if pin.index >= hash_bits as usize / 8 {
+                return Err(Error::msg(format!(
+                    "Byte {} is out of range for a {}-bit hash",
+                    pin.index, hash_bits
+                )));
+            }
+        }
+        if let Some(bound) = &self.less_than {
+            if bound.len() != hash_bits as usize / 4 {
+                return Err(Error::msg(format!(
+                    "{} is not a full {}-bit object id",
+                    bound, hash_bits
+                )));
+            }
+            if bound.max_leading_zero_bits().is_none() {
+                return Err(Error::msg(format!("No hash sorts before {}", bound)));
+            }
+        }
+        if let Some(unique) = &self.unique_abbrev {
+            if unique.len > 16 || unique.len > hash_bits as usize / 4 {
+                return Err(Error::msg(format!(
+                    "Cannot check {}-digit abbreviations for uniqueness",
+                    unique.len
+                )));
+            }
+            if unique.taken.len() as u128 == 1 << (4 * unique.len) {
+                return Err(Error::msg(format!(
+                    "Every {}-digit abbreviation is already taken",
+                    unique.len
+                )));
+            }
+        }
+        for prefix in self.prefixes.iter() {
+            let digits = &prefix.0[..prefix.len().min(self.repeat)];
+            if digits.iter().any(|&digit| digit != digits[0]) {
+                return Err(Error::msg(format!(
+                    "A hash starting with {} cannot start with {} repeated digits",
+                    prefix, self.repeat
+                )));
+            }
+            // The prefix pins down where the first set bit is, if it contains one
+            if let Some(max_zeros) = prefix.max_leading_zero_bits() {
+                if self.bits > max_zeros {
+                    return Err(Error::msg(format!(
+                        "A hash starting with {} has at most {} leading zero bits",
+                        prefix, max_zeros
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimates how many hashes it takes to meet the target on average. The constraints are
+    /// treated as independent of each other, apart from prefixes and leading zeros, which tend
+    /// to overlap. The regex is left out, since there's no telling how selective it is.
+    pub fn expected_attempts(&self, hash_bits: u16) -> f64 {
+        let mut p = if self.prefixes.is_empty() {
+            0.5f64.powi(self.bits as i32)
+        } else {
+            self.prefixes
+                .iter()
+                .map(|prefix| {
+                    // A prefix already determines how many leading zeros there are, unless it's
+                    // all zeros, in which case any zeros beyond it still need to be found
+                    let extra_bits = match prefix.max_leading_zero_bits() {
+                        Some(_) => 0,
+                        None => self.bits.saturating_sub(4 * prefix.len() as u16),
+                    };
+                    16f64.powi(-(prefix.len() as i32)) * 0.5f64.powi(extra_bits as i32)
+                })
+                .sum::<f64>()
+                .min(1.0)
+        };
+        p *= 0.5f64.powi(self.trailing_bits as i32);
+        if let Some(prefix) = &self.base32_prefix {
+            p *= 32f64.powi(-(prefix.0.len() as i32));
+        }
+        if self.repeat > 1 {
+            p *= 16f64.powi(1 - self.repeat as i32);
+        }
+        if let Some(suffix) = &self.suffix {
+            p *= 16f64.powi(-(suffix.len() as i32));
+        }
+        if let Some(pattern) = &self.contains {
+            let positions = (hash_bits as usize / 4 - pattern.len() + 1) as f64;
+            p *= (positions * 16f64.powi(-(pattern.len() as i32))).min(1.0);
+        }
+        if let Some(mask) = &self.mask {
+            let mask_bits: u32 = mask.mask.0.iter().map(|digit| digit.count_ones()).sum();
+            p *= 0.5f64.powi(mask_bits as i32);
+        }
+        p *= 256f64.powi(-(self.bytes.len() as i32));
+        if let Some(bound) = &self.less_than {
+            // The fraction of all hashes that sort before the bound
+            p *= bound
+                .0
+                .iter()
+                .rev()
+                .fold(0.0, |acc, &digit| (acc + digit as f64) / 16.0);
+        }
+        if let Some(unique) = &self.unique_abbrev {
+            p *= 1.0 - unique.taken.len() as f64 * 16f64.powi(-(unique.len as i32));
+        }
+        1.0 / p
+    }
+
+    /// Rates how close a hash comes to the target, for reporting progress. Each constraint
+    /// that can be partially met contributes the bits of it that are, up to its own difficulty,
+    /// so only hashes getting closer to the target as a whole improve the score.
+    pub fn score(&self, hash: &[u8], num_zeros: u16) -> u16 {
+        let mut score = num_zeros.min(self.bits);
+        if self.trailing_bits > 0 {
+            score += num_trailing_zero_bits(hash).min(self.trailing_bits);
+        }
+        if self.repeat > 1 {
+            score += 4 * (repeat_len(hash, self.repeat) - 1) as u16;
+        }
+        if let Some(prefix) = self.prefixes.closest(hash) {
+            score += 4 * prefix.matched_prefix_len(hash) as u16;
+        }
+        if let Some(suffix) = &self.suffix {
+            score += 4 * suffix.matched_suffix_len(hash) as u16;
+        }
+        if let Some(prefix) = &self.base32_prefix {
+            score += 5 * prefix.matched_prefix_len(hash) as u16;
+        }
+        score
+    }
+
+    /// Describes how far along a hash is for each constraint that can be partially met, other
+    /// than the leading zeros, which the caller reports in whichever unit the user asked for.
+    pub fn progress(&self, hash: &[u8]) -> Vec<String> {
+        let mut progress = Vec::new();
+        if self.trailing_bits > 0 {
+            progress.push(format!(
+                "{}/{} trailing zeros",
+                num_trailing_zero_bits(hash),
+                self.trailing_bits
+            ));
+        }
+        if self.repeat > 1 {
+            progress.push(format!(
+                "{}/{} repeated digits",
+                repeat_len(hash, self.repeat),
+                self.repeat
+            ));
+        }
+        if let Some(prefix) = self.prefixes.closest(hash) {
+            if self.prefixes.len() > 1 {
+                progress.push(format!(
+                    "{}/{} digits of prefix {}",
+                    prefix.matched_prefix_len(hash),
+                    prefix.len(),
+                    prefix
+                ));
+            } else {
+                progress.push(format!(
+                    "{}/{} prefix digits",
+                    prefix.matched_prefix_len(hash),
+                    prefix.len()
+                ));
+            }
+        }
+        if let Some(suffix) = &self.suffix {
+            progress.push(format!(
+                "{}/{} suffix digits",
+                suffix.matched_suffix_len(hash),
+                suffix.len()
+            ));
+        }
+        if let Some(prefix) = &self.base32_prefix {
+            progress.push(format!(
+                "{}/{} base32 digits",
+                prefix.matched_prefix_len(hash),
+                prefix.0.len()
+            ));
+        }
+        progress
+    }
+
+    /// Checks a hash against the target. The number of leading zeros is passed in since the
+    /// caller has already counted them to report progress.
+    pub fn matches(&self, hash: &[u8], num_zeros: u16) -> bool {
+        num_zeros >= self.bits
+            && (self.trailing_bits == 0 || num_trailing_zero_bits(hash) >= self.trailing_bits)
+            && repeat_len(hash, self.repeat) >= self.repeat
+            && (self.prefixes.is_empty() || self.prefixes.matching(hash).is_some())
+            && self
+                .suffix
+                .as_ref()
+                .is_none_or(|suffix| suffix.is_suffix_of(hash))
+            && self
+                .base32_prefix
+                .as_ref()
+                .is_none_or(|prefix| prefix.matched_prefix_len(hash) == prefix.0.len())
+            && self.mask.as_ref().is_none_or(|mask| mask.matches(hash))
+            && self.bytes.iter().all(|pin| hash[pin.index] == pin.value)
+            && self
+                .less_than
+                .as_ref()
+                .is_none_or(|bound| bound.is_above(hash))
+            && self
+                .contains
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_contained_in(hash))
+            && self
+                .unique_abbrev
+                .as_ref()
+                .is_none_or(|unique| unique.is_unique(hash))
+            // Converting the hash to hex is comparatively slow, so the regex goes last, and
+            // only gets evaluated once all the cheaper checks have passed.
+            && self
+                .regex
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(to_hex_digits(hash, &mut [0; 64])))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} leading zero bits", self.bits)?;
+        if self.trailing_bits > 0 {
+            write!(f, " and {} trailing zero bits", self.trailing_bits)?;
+        }
+        if self.repeat > 1 {
+            write!(f, " and {} repeated leading digits", self.repeat)?;
+        }
+        if self.prefixes.len() > 3 {
+            write!(f, " and one of {} prefixes", self.prefixes.len())?;
+        } else {
+            for (i, prefix) in self.prefixes.iter().enumerate() {
+                let sep = if i == 0 { " and prefix" } else { " or" };
+                write!(f, "{} {}", sep, prefix)?;
+            }
+        }
+        if let Some(suffix) = &self.suffix {
+            write!(f, " and suffix {}", suffix)?;
+        }
+        if let Some(prefix) = &self.base32_prefix {
+            write!(f, " and base32 prefix {}", prefix)?;
+        }
+        if let Some(pattern) = &self.contains {
+            write!(f, " and containing {}", pattern)?;
+        }
+        if let Some(mask) = &self.mask {
+            write!(f, " and mask {} with value {}", mask.mask, mask.value)?;
+        }
+        for pin in &self.bytes {
+            write!(f, " and byte {}", pin)?;
+        }
+        if let Some(bound) = &self.less_than {
+            write!(f, " and sorting before {}", bound)?;
+        }
+        if let Some(unique) = &self.unique_abbrev {
+            write!(f, " and a unique {}-digit abbreviation", unique.len)?;
+        }
+        if let Some(regex) = &self.regex {
+            write!(f, " and regex {}", regex)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prefixes the hash may start with, any one of which will do. To keep checking a hash fast
+/// even with thousands of them, they're kept sorted, and any prefix starting with another one
+/// is dropped as redundant. That way, the only prefix a hash can match is the one sorting right
+/// before it, and the prefix it has the most digits in common with is one of its neighbors.
+#[derive(Clone, Default)]
+pub struct PrefixSet(Vec<HexPattern>);
+
+impl PrefixSet {
+    pub fn new(mut prefixes: Vec<HexPattern>) -> Self {
+        prefixes.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut set: Vec<HexPattern> = Vec::with_capacity(prefixes.len());
+        for prefix in prefixes {
+            if set.last().is_none_or(|last| !prefix.0.starts_with(&last.0)) {
+                set.push(prefix);
+            }
+        }
+        Self(set)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, HexPattern> {
+        self.0.iter()
+    }
+
+    /// The number of prefixes sorting before the hash, counting those it starts with.
+    fn position(&self, hash: &[u8]) -> usize {
+        self.0.partition_point(|prefix| {
+            let digits = (0..prefix.len()).map(|i| nibble(hash, i));
+            prefix.0.iter().cloned().le(digits)
+        })
+    }
+
+    /// The prefix the hash starts with, if any.
+    pub fn matching(&self, hash: &[u8]) -> Option<&HexPattern> {
+        let idx = self.position(hash).checked_sub(1)?;
+        Some(&self.0[idx]).filter(|prefix| prefix.is_prefix_of(hash))
+    }
+
+    /// The prefix the hash has the most leading digits in common with.
+    pub fn closest(&self, hash: &[u8]) -> Option<&HexPattern> {
+        let idx = self.position(hash);
+        self.0[idx.saturating_sub(1)..(idx + 1).min(self.len())]
+            .iter()
+            .max_by_key(|prefix| prefix.matched_prefix_len(hash))
+    }
+}
+
+/// Requires the bits of the hash selected by `mask` to be equal to those of `value`. Both
+/// are aligned with the start of the hash, so a mask shorter than the hash covers only its
+/// leading digits.
+#[derive(Clone)]
+pub struct Mask {
+    pub mask: HexPattern,
+    pub value: HexPattern,
+}
+
+impl Mask {
+    pub fn matches(&self, hash: &[u8]) -> bool {
+        self.mask
+            .0
+            .iter()
+            .zip(&self.value.0)
+            .enumerate()
+            .all(|(i, (&mask, &value))| nibble(hash, i) & mask == value & mask)
+    }
+}
+
+/// The digits of Crockford's base32, which leaves out I, L, O and U to avoid confusion.
+const BASE32_DIGITS: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A string of Crockford base32 digits, stored as one 5-bit value per byte.
+#[derive(Clone)]
+pub struct Base32Pattern(Vec<u8>);
+
+impl Base32Pattern {
+    /// How many digits at the start of the pattern the start of the hash agrees with.
+    pub fn matched_prefix_len(&self, hash: &[u8]) -> usize {
+        self.0
+            .iter()
+            .enumerate()
+            .take_while(|&(i, &digit)| quintet(hash, i) == digit)
+            .count()
+    }
+}
+
+impl FromStr for Base32Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.chars()
+            .map(|c| {
+                // Crockford's base32 reads the letters that were left out as the digits they
+                // look like, regardless of case
+                let c = match c.to_ascii_uppercase() {
+                    'O' => '0',
+                    'I' | 'L' => '1',
+                    c => c,
+                };
+                BASE32_DIGITS
+                    .iter()
+                    .position(|&digit| digit as char == c)
+                    .map(|digit| digit as u8)
+                    .ok_or_else(|| Error::msg(format!("Invalid base32 digit '{}' in {}", c, s)))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for Base32Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &digit in &self.0 {
+            write!(f, "{}", BASE32_DIGITS[digit as usize] as char)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a hash in Crockford's base32, starting from the most significant bits. Since the
+/// length of a hash isn't a multiple of 5 bits, the last digit gets padded with zeros.
+pub fn to_base32(hash: &[u8]) -> String {
+    (0..(8 * hash.len()).div_ceil(5))
+        .map(|i| BASE32_DIGITS[quintet(hash, i) as usize] as char)
+        .collect()
+}
+
+/// Gets the `i`th base32 digit of a hash, counting from the most significant one.
+fn quintet(hash: &[u8], i: usize) -> u8 {
+    let bit = 5 * i;
+    let hi = hash[bit / 8] as u16;
+    let lo = hash.get(bit / 8 + 1).cloned().unwrap_or(0) as u16;
+    ((hi << 8 | lo) >> (11 - bit % 8)) as u8 & 0x1F
+}
+
+/// Requires the byte of the hash at `index` to be equal to `value`.
+#[derive(Clone)]
+pub struct BytePin {
+    pub index: usize,
+    pub value: u8,
+}
+
+impl FromStr for BytePin {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::msg(format!("Expected <index>=<hex byte>, got {}", s));
+        let (index, value) = s.split_once('=').ok_or_else(invalid)?;
+        if value.len() != 2 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            index: index.parse().map_err(|_| invalid())?,
+            value: u8::from_str_radix(value, 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for BytePin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={:02x}", self.index, self.value)
+    }
+}
+
+/// The abbreviated ids of all objects already in the repository, which the abbreviated id of
+/// the mined hash must not collide with. Abbreviations are stored as numbers, sorted so that
+/// checking a hash is a binary search.
+#[derive(Clone)]
+pub struct UniqueAbbrev {
+    len: usize,
+    taken: Vec<u64>,
+}
+
+impl UniqueAbbrev {
+    pub fn new(len: usize, ids: Vec<Vec<u8>>) -> Self {
+        let mut taken: Vec<_> = ids.iter().map(|id| abbrev(id, len)).collect();
+        taken.sort_unstable();
+        taken.dedup();
+        Self { len, taken }
+    }
+
+    pub fn is_unique(&self, hash: &[u8]) -> bool {
+        self.taken.binary_search(&abbrev(hash, self.len)).is_err()
+    }
+}
+
+/// Reads the first `len` hex digits of a hash as a number.
+fn abbrev(hash: &[u8], len: usize) -> u64 {
+    (0..len.min(16)).fold(0, |acc, i| acc << 4 | nibble(hash, i) as u64)
+}
+
+/// A string of hex digits, stored as one nibble per byte so that odd lengths work.
+#[derive(Clone)]
+pub struct HexPattern(Vec<u8>);
+
+impl HexPattern {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// How many leading zero bits a hash starting with this pattern can have, or `None` if
+    /// the pattern is all zeros.
+    pub fn max_leading_zero_bits(&self) -> Option<u16> {
+        let idx = self.0.iter().position(|&digit| digit != 0)?;
+        Some(4 * idx as u16 + self.0[idx].leading_zeros() as u16 - 4)
+    }
+
+    pub fn is_prefix_of(&self, hash: &[u8]) -> bool {
+        self.0
+            .iter()
+            .enumerate()
+            .all(|(i, &digit)| nibble(hash, i) == digit)
+    }
+
+    /// Whether the pattern occurs anywhere in the hash, at any digit rather than only at byte
+    /// boundaries.
+    pub fn is_contained_in(&self, hash: &[u8]) -> bool {
+        (0..=2 * hash.len() - self.len()).any(|start| {
+            self.0
+                .iter()
+                .enumerate()
+                .all(|(i, &digit)| nibble(hash, start + i) == digit)
+        })
+    }
+
+    /// Whether a hash compares below this pattern, digit by digit from the start. For a
+    /// pattern as long as the hash, that's the same as comparing the ids as big-endian bytes.
+    pub fn is_above(&self, hash: &[u8]) -> bool {
+        (0..self.len())
+            .map(|i| nibble(hash, i))
+            .lt(self.0.iter().cloned())
+    }
+
+    /// How many digits at the start of the pattern the start of the hash agrees with.
+    pub fn matched_prefix_len(&self, hash: &[u8]) -> usize {
+        self.0
+            .iter()
+            .enumerate()
+            .take_while(|&(i, &digit)| nibble(hash, i) == digit)
+            .count()
+    }
+
+    /// How many digits at the end of the pattern the end of the hash agrees with.
+    pub fn matched_suffix_len(&self, hash: &[u8]) -> usize {
+        let end = 2 * hash.len();
+        self.0
+            .iter()
+            .rev()
+            .enumerate()
+            .take_while(|&(i, &digit)| nibble(hash, end - 1 - i) == digit)
+            .count()
+    }
+
+    pub fn is_suffix_of(&self, hash: &[u8]) -> bool {
+        let start = 2 * hash.len() - self.len();
+        self.0
+            .iter()
+            .enumerate()
+            .all(|(i, &digit)| nibble(hash, start + i) == digit)
+    }
+}
+
+impl FromStr for HexPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.chars()
+            .map(|c| {
+                c.to_digit(16)
+                    .map(|digit| digit as u8)
+                    .ok_or_else(|| Error::msg(format!("Invalid hex digit '{}' in {}", c, s)))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for HexPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for digit in &self.0 {
+            write!(f, "{:x}", digit)?;
+        }
+        Ok(())
+    }
+}
+
+/// A table for spelling words in hex, by substituting letters that aren't hex digits with
+/// digits that look like them.
+#[derive(Clone)]
+pub struct Leet(Vec<(char, char)>);
+
+impl Leet {
+    pub fn spell(&self, word: &str) -> Result<HexPattern> {
+        word.to_lowercase()
+            .chars()
+            .map(|c| match self.0.iter().find(|&&(letter, _)| letter == c) {
+                Some(&(_, digit)) => digit,
+                None => c,
+            })
+            .collect::<String>()
+            .parse()
+            .map_err(|_| Error::msg(format!("Cannot spell {} in hex", word)))
+    }
+}
+
+impl FromStr for Leet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split(',')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut chars = pair.chars();
+                match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                    (Some(letter), Some('='), Some(digit), None) if digit.is_ascii_hexdigit() => {
+                        Ok((letter.to_ascii_lowercase(), digit.to_ascii_lowercase()))
+                    }
+                    _ => Err(Error::msg(format!("Invalid substitution {}", pair))),
+                }
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+/// Writes out the lowercase hex representation of a hash without allocating.
+fn to_hex_digits<'a>(hash: &[u8], buf: &'a mut [u8; 64]) -> &'a [u8] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for (i, &byte) in hash.iter().enumerate() {
+        buf[2 * i] = DIGITS[(byte >> 4) as usize];
+        buf[2 * i + 1] = DIGITS[(byte & 0xF) as usize];
+    }
+    &buf[..2 * hash.len()]
+}
+
+/// Counts how many of the leading digits of a hash are equal to the first one, up to `max`.
+fn repeat_len(hash: &[u8], max: usize) -> usize {
+    1 + (1..max)
+        .take_while(|&i| nibble(hash, i) == nibble(hash, 0))
+        .count()
+}
+
+/// Gets the `i`th hex digit of a hash, counting from the most significant one.
+fn nibble(hash: &[u8], i: usize) -> u8 {
+    let byte = hash[i / 2];
+    if i.is_multiple_of(2) {
+        byte >> 4
+    } else {
+        byte & 0xF
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::num_leading_zero_bits;
+    use crate::repo::from_hex;
+
+    fn prefix(prefixes: &[&str]) -> Target {
+        Target {
+            prefixes: PrefixSet::new(prefixes.iter().map(|p| p.parse().unwrap()).collect()),
+            ..Target::default()
+        }
+    }
+
+    fn check(target: &Target, hash: &str) -> bool {
+        let hash = from_hex(hash).unwrap();
+        target.matches(&hash, num_leading_zero_bits(&hash))
+    }
+
+    #[test]
+    fn odd_nibble_prefixes() {
+        let hash = "cafe5a0000000000000000000000000000000000";
+        assert!(check(&prefix(&["c"]), hash));
+        assert!(check(&prefix(&["caf"]), hash));
+        assert!(check(&prefix(&["cafe5"]), hash));
+        assert!(!check(&prefix(&["cafe4"]), hash));
+        assert!(!check(&prefix(&["cafe5a1"]), hash));
+        // Only the prefix the hash sorts after can match, so the others mustn't get in the way
+        assert!(check(&prefix(&["0", "caf", "cb", "d"]), hash));
+        assert!(!check(&prefix(&["0", "ca0", "cb"]), hash));
+
+        let target = prefix(&["cafe5"]);
+        assert_eq!(
+            target
+                .prefixes
+                .matching(&from_hex(hash).unwrap())
+                .unwrap()
+                .len(),
+            5
+        );
+        assert_eq!(target.expected_attempts(160), 16f64.powi(5));
+    }
+
+    #[test]
+    fn odd_nibble_prefixes_combine_with_bits() {
+        let hash = "0007ff0000000000000000000000000000000000";
+        let mut target = prefix(&["000"]);
+        target.bits = 13;
+        assert!(check(&target, hash));
+        target.bits = 14;
+        assert!(!check(&target, hash));
+        // With a digit that isn't zero, the prefix decides how many leading zeros there are
+        let prefix = "0007".parse::<HexPattern>().unwrap();
+        assert_eq!(prefix.max_leading_zero_bits(), Some(13));
+        assert_eq!(
+            "000".parse::<HexPattern>().unwrap().max_leading_zero_bits(),
+            None
+        );
+    }
+
+    #[test]
+    fn invalid_prefixes_are_an_error() {
+        assert!("cafg".parse::<HexPattern>().is_err());
+        assert!("é".parse::<HexPattern>().is_err());
+    }
+
+    fn validate(target: Target, hash_bits: u16) -> String {
+        match target.validate(hash_bits) {
+            Ok(()) => "ok".to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn targets_wider_than_the_hash_are_rejected() {
+        let bits = |bits| Target {
+            bits,
+            ..Target::default()
+        };
+        assert_eq!(validate(bits(160), 160), "ok");
+        assert_eq!(
+            validate(bits(161), 160),
+            "Cannot find 161 leading zero bits in a 160-bit hash"
+        );
+        assert_eq!(validate(bits(200), 256), "ok");
+
+        let target = Target {
+            bits: 100,
+            trailing_bits: 61,
+            ..Target::default()
+        };
+        assert_eq!(
+            validate(target, 160),
+            "Cannot find 100 leading and 61 trailing zero bits in a 160-bit hash"
+        );
+
+        let repeat = |repeat| Target {
+            repeat,
+            ..Target::default()
+        };
+        assert_eq!(validate(repeat(40), 160), "ok");
+        assert_eq!(
+            validate(repeat(41), 160),
+            "Cannot repeat a digit 41 times in a 160-bit hash"
+        );
+    }
+
+    #[test]
+    fn leading_and_trailing_bits_share_the_hash() {
+        let target = |bits, trailing_bits| Target {
+            bits,
+            trailing_bits,
+            ..Target::default()
+        };
+        assert_eq!(validate(target(80, 80), 160), "ok");
+        assert_eq!(
+            validate(target(80, 81), 160),
+            "Cannot find 80 leading and 81 trailing zero bits in a 160-bit hash"
+        );
+        // Their sum doesn't fit a u16
+        assert_eq!(
+            validate(target(10, u16::MAX), 160),
+            "Cannot find 10 leading and 65535 trailing zero bits in a 160-bit hash"
+        );
+    }
+
+    /// The score of a hash, which is how much of the target it meets.
+    fn score(target: &Target, hash: &str) -> u16 {
+        let hash = from_hex(hash).unwrap();
+        target.score(&hash, num_leading_zero_bits(&hash))
+    }
+
+    #[test]
+    fn suffixes_match_the_last_digits() {
+        let target = Target {
+            suffix: Some("eef".parse().unwrap()),
+            ..Target::default()
+        };
+        assert!(check(&target, "beef00000000000000000000000000000000beef"));
+        assert!(check(&target, "ffffffffffffffffffffffffffffffffffffdeef"));
+        assert!(!check(&target, "beef00000000000000000000000000000000bee0"));
+        assert!(!check(&target, "eef0000000000000000000000000000000000000"));
+        // Each digit at the end counts, up to the first one that's off
+        assert_eq!(
+            score(&target, "000000000000000000000000000000000000000f"),
+            4
+        );
+        assert_eq!(
+            score(&target, "00000000000000000000000000000000000000ef"),
+            8
+        );
+        assert_eq!(
+            score(&target, "0000000000000000000000000000000000000ef0"),
+            0
+        );
+    }
+
+    #[test]
+    fn regexes_match_the_lowercase_hex() {
+        let target = Target {
+            regex: Some("^dead.*be+f$".parse().unwrap()),
+            ..Target::default()
+        };
+        assert!(check(&target, "DEAD00000000000000000000000000000000beef"));
+        assert!(check(&target, "deadbeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeef"));
+        assert!(!check(&target, "dead00000000000000000000000000000000bee0"));
+        // However long the hash is
+        let hash = format!("dead{}beef", "0".repeat(56));
+        assert!(check(&target, &hash));
+        // The other conditions still have to hold, and only they count towards the score
+        let target = Target { bits: 1, ..target };
+        assert!(!check(&target, "dead00000000000000000000000000000000beef"));
+        assert_eq!(
+            score(&target, "dead00000000000000000000000000000000beef"),
+            0
+        );
+    }
+
+    #[test]
+    fn repeats_count_the_leading_digits_alike() {
+        let target = Target {
+            repeat: 4,
+            ..Target::default()
+        };
+        assert!(check(&target, "cccc000000000000000000000000000000000000"));
+        assert!(check(&target, "0000000000000000000000000000000000000000"));
+        assert!(!check(&target, "ccc0cccc00000000000000000000000000000000"));
+        // The first digit doesn't count, being alike to itself
+        assert_eq!(
+            score(&target, "c000000000000000000000000000000000000000"),
+            0
+        );
+        assert_eq!(
+            score(&target, "ccc0000000000000000000000000000000000000"),
+            8
+        );
+        assert_eq!(
+            score(&target, "cccccccc00000000000000000000000000000000"),
+            12
+        );
+    }
+
+    #[test]
+    fn masks_select_the_bits_to_match() {
+        let mask = |mask: &str, value: &str| Target {
+            mask: Some(Mask {
+                mask: mask.parse().unwrap(),
+                value: value.parse().unwrap(),
+            }),
+            ..Target::default()
+        };
+        let target = mask("f0f8", "a0b8");
+        assert!(check(&target, "a5bf000000000000000000000000000000000000"));
+        assert!(check(&target, "a0b8ffffffffffffffffffffffffffffffffffff"));
+        assert!(!check(&target, "a5b7000000000000000000000000000000000000"));
+        assert!(!check(&target, "a5c8000000000000000000000000000000000000"));
+        assert!(!check(&target, "b5b8000000000000000000000000000000000000"));
+        // Only the bits in the mask matter, whatever else the value has
+        assert!(check(
+            &mask("0f", "ff"),
+            "0f00000000000000000000000000000000000000"
+        ));
+        assert!(mask("ff", "f").validate(160).is_err());
+        assert_eq!(mask("f0f8", "a0b8").expected_attempts(160), 2f64.powi(9));
+    }
+
+    #[test]
+    fn less_than_compares_whole_ids() {
+        let bound = "8000000000000000000000000000000000000001";
+        let target = Target {
+            less_than: Some(bound.parse().unwrap()),
+            ..Target::default()
+        };
+        assert!(check(&target, "0000000000000000000000000000000000000000"));
+        assert!(check(&target, "8000000000000000000000000000000000000000"));
+        assert!(check(&target, "7fffffffffffffffffffffffffffffffffffffff"));
+        // Sorting before isn't the same as being equal
+        assert!(!check(&target, bound));
+        assert!(!check(&target, "8000000000000000000000000000000000000002"));
+        assert!(!check(&target, "9000000000000000000000000000000000000000"));
+        assert!((target.expected_attempts(160) - 2.0).abs() < 1e-9);
+        assert!(target.validate(160).is_ok());
+        assert!(target.validate(256).is_err());
+    }
+
+    #[test]
+    fn prefixes_and_bits_add_up() {
+        let mut target = prefix(&["cafe"]);
+        target.bits = 0;
+        assert_eq!(
+            score(&target, "caf0000000000000000000000000000000000000"),
+            12
+        );
+        // Zeros beyond an all-zero prefix are still to be found, while any other prefix
+        // already settles how many leading zeros there are
+        let mut target = prefix(&["00"]);
+        target.bits = 12;
+        assert_eq!(target.expected_attempts(160), 2f64.powi(12));
+        assert!(check(&target, "000f000000000000000000000000000000000000"));
+        assert!(!check(&target, "001f000000000000000000000000000000000000"));
+        assert_eq!(
+            score(&target, "001f000000000000000000000000000000000000"),
+            11 + 8
+        );
+        let mut target = prefix(&["0007"]);
+        target.bits = 13;
+        assert!(target.validate(160).is_ok());
+        target.bits = 14;
+        assert_eq!(
+            validate(target, 160),
+            "A hash starting with 0007 has at most 13 leading zero bits"
+        );
+    }
+
+    #[test]
+    fn any_of_several_prefixes_will_do() {
+        let target = prefix(&["beef", "cafe", "dead"]);
+        for hash in [
+            "beef000000000000000000000000000000000000",
+            "cafe000000000000000000000000000000000000",
+            "deadffffffffffffffffffffffffffffffffffff",
+        ] {
+            assert!(check(&target, hash), "{}", hash);
+            let matched = target.prefixes.matching(&from_hex(hash).unwrap());
+            assert_eq!(matched.unwrap().to_string(), hash[..4]);
+        }
+        assert!(!check(&target, "cafd000000000000000000000000000000000000"));
+        // The score goes by whichever prefix the hash comes closest to
+        assert_eq!(
+            score(&target, "dea0000000000000000000000000000000000000"),
+            12
+        );
+        assert_eq!(
+            score(&target, "cb00000000000000000000000000000000000000"),
+            4
+        );
+        assert_eq!(
+            score(&target, "0000000000000000000000000000000000000000"),
+            0
+        );
+        assert_eq!(target.expected_attempts(160), 16f64.powi(4) / 3.0);
+    }
+
+    #[test]
+    fn unique_abbreviations_avoid_existing_ids() {
+        let ids = [
+            "cafe000000000000000000000000000000000000",
+            "cafe111111111111111111111111111111111111",
+            "beef000000000000000000000000000000000000",
+        ];
+        let ids = ids.iter().map(|id| from_hex(id).unwrap()).collect();
+        let target = Target {
+            unique_abbrev: Some(UniqueAbbrev::new(4, ids)),
+            ..Target::default()
+        };
+        assert!(!check(&target, "cafeffffffffffffffffffffffffffffffffffff"));
+        assert!(!check(&target, "beef000000000000000000000000000000000000"));
+        assert!(check(&target, "caff000000000000000000000000000000000000"));
+        assert!(check(&target, "0000000000000000000000000000000000000000"));
+        // The two ids starting with cafe take up just the one abbreviation
+        let p = 1.0 / target.expected_attempts(160);
+        assert!((p - (1.0 - 2.0 / 65536.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn contained_patterns_can_start_at_any_digit() {
+        let target = Target {
+            contains: Some("beef".parse().unwrap()),
+            ..Target::default()
+        };
+        assert!(check(&target, "beef000000000000000000000000000000000000"));
+        assert!(check(&target, "00000000000000000000000000000000000beef0"));
+        assert!(check(&target, "000000000000000000000000000000000000beef"));
+        // Starting halfway through a byte
+        assert!(check(&target, "0000000beef00000000000000000000000000000"));
+        assert!(!check(&target, "0000000bee0f0000000000000000000000000000"));
+        assert!(!check(&target, "eef0000000000000000000000000000000000bee"));
+    }
+
+    #[test]
+    fn words_are_spelled_in_hex() {
+        let leet: Leet = "o=0,i=1,l=1,z=2,s=5,t=7,g=9".parse().unwrap();
+        assert_eq!(leet.spell("Coffee").unwrap().to_string(), "c0ffee");
+        assert_eq!(leet.spell("deadbeef").unwrap().to_string(), "deadbeef");
+        assert_eq!(leet.spell("c0de").unwrap().to_string(), "c0de");
+        assert_eq!(leet.spell("SALT").unwrap().to_string(), "5a17");
+        assert_eq!(
+            leet.spell("hello").err().unwrap().to_string(),
+            "Cannot spell hello in hex"
+        );
+        // A table of its own replaces the default one altogether
+        let leet: Leet = "O=0".parse().unwrap();
+        assert_eq!(leet.spell("food").unwrap().to_string(), "f00d");
+        assert!(leet.spell("toast").is_err());
+        assert!("".parse::<Leet>().unwrap().spell("face").is_ok());
+        for invalid in ["o0", "o=x", "o=00", "=0"] {
+            assert!(invalid.parse::<Leet>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn trailing_bits_combine_with_leading_bits() {
+        let target = Target {
+            bits: 8,
+            trailing_bits: 12,
+            ..Target::default()
+        };
+        assert!(check(&target, "00ff000000000000000000000000000000001000"));
+        assert!(!check(&target, "00ff000000000000000000000000000000000800"));
+        assert!(!check(&target, "01ff000000000000000000000000000000001000"));
+        // Zeros at either end count, each only up to what's asked for
+        assert_eq!(
+            score(&target, "0000000000000000000000000000000000000001"),
+            8
+        );
+        assert_eq!(
+            score(&target, "ff00000000000000000000000000000000000000"),
+            12
+        );
+        assert_eq!(
+            score(&target, "0100000000000000000000000000000000000800"),
+            7 + 11
+        );
+        assert_eq!(target.expected_attempts(160), 2f64.powi(20));
+    }
+
+    #[test]
+    fn bytes_are_pinned_by_index() {
+        let pin = |s: &str| s.parse::<BytePin>().unwrap();
+        let target = Target {
+            bytes: vec![pin("0=ca"), pin("19=fe")],
+            ..Target::default()
+        };
+        assert!(check(&target, "ca000000000000000000000000000000000000fe"));
+        assert!(!check(&target, "ca000000000000000000000000000000000000ff"));
+        assert!(!check(&target, "0ca00000000000000000000000000000000000fe"));
+        assert_eq!(pin("7=0A").to_string(), "7=0a");
+        for invalid in ["7", "7=a", "7=abc", "x=ab", "-1=ab", "7=gg"] {
+            assert!(invalid.parse::<BytePin>().is_err(), "{}", invalid);
+        }
+        // Past the last byte of a SHA-1 hash, though not of a SHA-256 one
+        let target = Target {
+            bytes: vec![pin("20=00")],
+            ..Target::default()
+        };
+        assert!(target.validate(160).is_err());
+        assert!(target.validate(256).is_ok());
+    }
+
+    #[test]
+    fn attempts_double_with_every_bit() {
+        let bits = |bits| Target {
+            bits,
+            ..Target::default()
+        };
+        assert_eq!(bits(0).expected_attempts(160), 1.0);
+        assert_eq!(bits(1).expected_attempts(160), 2.0);
+        assert_eq!(bits(20).expected_attempts(160), 1048576.0);
+        assert_eq!(bits(20).expected_attempts(256), 1048576.0);
+    }
+
+    #[test]
+    fn base32_follows_crockford() {
+        let vectors: [(&str, &str); 5] = [
+            ("00", "00"),
+            ("ff", "ZW"),
+            ("48656c6c6f", "91JPRV3F"),
+            ("cafebabe", "SBZBNFG"),
+            (
+                "0123456789abcdef0123456789abcdef01234567",
+                "04HMASW9NF6YY0938NKRKAYDXW0J6HB7",
+            ),
+        ];
+        for (hex, base32) in vectors {
+            assert_eq!(to_base32(&from_hex(hex).unwrap()), base32, "{}", hex);
+        }
+        // Letters that could be mistaken for digits are read as those digits
+        let pattern: Base32Pattern = "o4hmilas".parse().unwrap();
+        assert_eq!(pattern.to_string(), "04HM11AS");
+        assert!("04U".parse::<Base32Pattern>().is_err());
+
+        let target = Target {
+            base32_prefix: Some("04HMA".parse().unwrap()),
+            ..Target::default()
+        };
+        assert!(check(&target, "0123456789abcdef0123456789abcdef01234567"));
+        assert!(!check(&target, "0123466789abcdef0123456789abcdef01234567"));
+        assert_eq!(
+            score(&target, "0123400000000000000000000000000000000000"),
+            20
+        );
+    }
+
+    #[test]
+    fn prefix_sets_scale_to_many_prefixes() {
+        use sha1::{Digest, Sha1};
+        // A few hundred prefixes of different lengths, some of which start with others
+        let mut patterns: Vec<String> = (0..300u32)
+            .map(|i| format!("{:x}", i.wrapping_mul(2654435761) >> (8 + i % 12)))
+            .collect();
+        patterns.extend(
+            ["abc", "abcd", "abc0", "0", "00000000"]
+                .iter()
+                .map(|p| p.to_string()),
+        );
+        let parsed: Vec<HexPattern> = patterns.iter().map(|p| p.parse().unwrap()).collect();
+        let set = PrefixSet::new(parsed.clone());
+        // Dropping what's redundant leaves every prefix that doesn't start with another
+        let kept: Vec<String> = set.iter().map(|p| p.to_string()).collect();
+        for pattern in &patterns {
+            let covered = patterns
+                .iter()
+                .any(|other| other != pattern && pattern.starts_with(other.as_str()));
+            assert_eq!(kept.contains(pattern), !covered, "{}", pattern);
+        }
+        assert!(kept.contains(&"abc".to_string()) && !kept.contains(&"abcd".to_string()));
+
+        // Every hash matches exactly when one of the prefixes is a prefix of it, and comes
+        // closest to one of the prefixes kept that it has the most digits in common with
+        let hashes = (0..2000u32)
+            .map(|i| Sha1::digest(&i.to_le_bytes()).to_vec())
+            .chain(parsed.iter().map(|p| {
+                let mut hash = from_hex(&format!("{:0<40}", p.to_string())).unwrap();
+                hash[19] = 0xff;
+                hash
+            }));
+        for hash in hashes {
+            let any = parsed.iter().any(|p| p.is_prefix_of(&hash));
+            let matched = set.matching(&hash);
+            assert_eq!(matched.is_some(), any, "{:02x?}", hash);
+            assert!(matched.is_none_or(|p| p.is_prefix_of(&hash)));
+            let best = set.iter().map(|p| p.matched_prefix_len(&hash)).max();
+            let closest = set.closest(&hash).map(|p| p.matched_prefix_len(&hash));
+            assert_eq!(closest, best, "{:02x?}", hash);
+        }
+    }
+}
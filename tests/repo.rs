@@ -0,0 +1,897 @@
+// Mining commits in actual repositories, built from scratch with the `git` binary for every
+// test, and checking that what comes out is still a valid commit as far as `git fsck --strict`
+// is concerned.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// A repository in a directory of its own, removed again once the test is done with it.
+struct TestRepo {
+    dir: PathBuf,
+}
+
+impl TestRepo {
+    /// Creates an empty repository, named after the test so that tests can run in parallel.
+    fn new(name: &str) -> Self {
+        Self::init(name, &[])
+    }
+
+    /// Like `new`, with extra arguments for `git init`.
+    fn init(name: &str, args: &[&str]) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("git-power-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let repo = Self { dir };
+        repo.git(&[&["init", "-q", "-b", "main"], args].concat());
+        repo
+    }
+
+    fn commit(&self, message: &str) {
+        self.git(&["commit", "-q", "--allow-empty", "-m", message]);
+    }
+
+    fn git(&self, args: &[&str]) -> String {
+        self.git_in(&self.dir, args)
+    }
+
+    fn git_in(&self, dir: &Path, args: &[&str]) -> String {
+        let output = run(self.command("git", dir).args(args), None);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    fn rev_parse(&self, revspec: &str) -> String {
+        self.git(&["rev-parse", revspec])
+    }
+
+    /// The raw contents of a commit.
+    fn cat_commit(&self, revspec: &str) -> Vec<u8> {
+        let output = run(
+            self.command("git", &self.dir)
+                .args(["cat-file", "commit"])
+                .arg(revspec),
+            None,
+        );
+        output.stdout
+    }
+
+    /// Runs git-power from `dir`, failing the test if it fails.
+    fn power_in(&self, dir: &Path, args: &[&str]) -> Output {
+        run(
+            self.command(env!("CARGO_BIN_EXE_git-power"), dir)
+                .args(["--threads", "2"])
+                .args(args),
+            None,
+        )
+    }
+
+    fn power(&self, args: &[&str]) -> Output {
+        self.power_in(&self.dir, args)
+    }
+
+    /// Runs git-power expecting it to fail, failing the test if it doesn't.
+    fn power_fails(&self, args: &[&str]) -> Output {
+        let output = self
+            .command(env!("CARGO_BIN_EXE_git-power"), &self.dir)
+            .args(["--threads", "2"])
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "git-power {:?} succeeded", args);
+        output
+    }
+
+    /// Checks the whole repository, on top of what git-power checks itself.
+    fn fsck(&self) {
+        self.fsck_in(&self.dir);
+    }
+
+    fn fsck_in(&self, dir: &Path) {
+        let output = run(
+            self.command("git", dir)
+                .args(["fsck", "--strict", "--no-progress", "--no-dangling"]),
+            None,
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.trim().is_empty(), "git fsck --strict: {}", stderr);
+    }
+
+    /// Generates an SSH key to sign with, returning the `user.signingKey` setting for it, or
+    /// nothing if ssh-keygen isn't available.
+    fn ssh_signing_key(&self) -> Option<String> {
+        let key = self.dir.join("key");
+        let keygen = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(&key)
+            .stdout(Stdio::null())
+            .status();
+        keygen
+            .is_ok_and(|status| status.success())
+            .then(|| format!("user.signingKey={}", key.display()))
+    }
+
+    /// A command that doesn't pick up any configuration from outside the repository, with an
+    /// author and committer set up to make commits with.
+    fn command(&self, program: &str, dir: &Path) -> Command {
+        let mut command = Command::new(program);
+        command
+            .current_dir(dir)
+            .env("HOME", &self.dir)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_AUTHOR_NAME", "A U Thor")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_AUTHOR_DATE", "1700000000 +0000")
+            .env("GIT_COMMITTER_NAME", "C O Mitter")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .env("GIT_COMMITTER_DATE", "1700000000 +0000")
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE")
+            .env_remove("GIT_POWER_BITS")
+            .env_remove("GIT_POWER_THREADS");
+        command
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Runs a command to completion, feeding it `stdin`, and fails the test if the command fails.
+fn run(command: &mut Command, stdin: Option<&[u8]>) -> Output {
+    let input = if stdin.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    command
+        .stdin(input)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().unwrap();
+    if let Some(stdin) = stdin {
+        // Dropping the pipe once it's written closes it, so that the command sees the end
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        command,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// The commit with its nonce header taken out again, which should be exactly what it was
+/// before being mined.
+fn without_nonce(commit: &[u8]) -> Vec<u8> {
+    let start = find(commit, b"\nnonce ").expect("No nonce header") + 1;
+    let end = start + find(&commit[start..], b"\n").unwrap() + 1;
+    [&commit[..start], &commit[end..]].concat()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn assert_mined(repo: &TestRepo, revspec: &str) {
+    let id = repo.rev_parse(revspec);
+    assert!(id.starts_with("00"), "{} is {}", revspec, id);
+}
+
+#[test]
+fn mined_commits_pass_fsck() {
+    let repo = TestRepo::new("fsck");
+    repo.commit("First");
+    repo.commit("Second\n\nWith a body.");
+    let original = repo.cat_commit("HEAD");
+    repo.power(&["--bits", "8"]);
+    assert_mined(&repo, "HEAD");
+    repo.fsck();
+
+    // The nonce goes after every header git knows about, right before the message
+    let mined = repo.cat_commit("HEAD");
+    let nonce = find(&mined, b"\nnonce ").unwrap();
+    assert!(find(&mined, b"\ncommitter ").unwrap() < nonce);
+    let message = find(&mined, b"\n\n").unwrap();
+    assert!(nonce < message && find(&mined[nonce + 1..message], b"\n").is_none());
+    assert_eq!(without_nonce(&mined), original);
+}
+
+#[test]
+fn mines_in_a_bare_repository() {
+    let repo = TestRepo::new("bare");
+    repo.commit("Initial commit");
+    repo.git(&["clone", "-q", "--bare", ".", "bare.git"]);
+    let bare = repo.dir.join("bare.git");
+    repo.power_in(&bare, &["--bits", "8"]);
+    let head = repo.git_in(&bare, &["rev-parse", "HEAD"]);
+    assert!(head.starts_with("00"), "HEAD is {}", head);
+    assert_eq!(repo.git_in(&bare, &["rev-parse", "main"]), head);
+    repo.fsck_in(&bare);
+    // The repository it was cloned from is left alone
+    assert!(!repo.rev_parse("HEAD").starts_with("00"));
+}
+
+#[test]
+fn keeps_the_encoding_header() {
+    let repo = TestRepo::new("encoding");
+    std::fs::write(repo.dir.join("message"), b"Caf\xe9 cr\xe8me\n").unwrap();
+    repo.git(&[
+        "-c",
+        "i18n.commitEncoding=ISO-8859-1",
+        "commit",
+        "-q",
+        "--allow-empty",
+        "-F",
+        "message",
+    ]);
+    let original = repo.cat_commit("HEAD");
+    assert!(find(&original, b"\nencoding ISO-8859-1\n").is_some());
+    repo.power(&["--bits", "8"]);
+    assert_mined(&repo, "HEAD");
+    repo.fsck();
+
+    // Byte for byte what it was, with the nonce following the encoding
+    let mined = repo.cat_commit("HEAD");
+    assert!(find(&mined, b"\nencoding ISO-8859-1\nnonce ").is_some());
+    assert_eq!(without_nonce(&mined), original);
+    let subject = repo.git(&["log", "-1", "--encoding=UTF-8", "--format=%s"]);
+    assert_eq!(subject, "Café crème");
+}
+
+#[test]
+fn keeps_the_mergetag_header() {
+    // git only records a mergetag for signed tags, and SSH keys are the easiest to sign with
+    let repo = TestRepo::new("mergetag");
+    let Some(signing_key) = repo.ssh_signing_key() else {
+        eprintln!("Skipping the mergetag test, since ssh-keygen isn't available");
+        return;
+    };
+    repo.commit("Initial commit");
+    repo.git(&["checkout", "-q", "-b", "side"]);
+    repo.commit("On the side");
+    repo.git(&[
+        "-c",
+        "gpg.format=ssh",
+        "-c",
+        &signing_key,
+        "tag",
+        "-s",
+        "v1",
+        "-m",
+        "v1",
+    ]);
+    repo.git(&["checkout", "-q", "main"]);
+    repo.commit("On main");
+    repo.git(&["merge", "-q", "--no-ff", "--no-edit", "v1"]);
+    let original = repo.cat_commit("HEAD");
+    assert!(find(&original, b"\nmergetag object ").is_some());
+
+    repo.power(&["--bits", "8"]);
+    assert_mined(&repo, "HEAD");
+    repo.fsck();
+    // The nonce comes after the last continuation line of the mergetag
+    let mined = repo.cat_commit("HEAD");
+    assert!(find(&mined, b"-----END SSH SIGNATURE-----\nnonce ").is_some());
+    assert_eq!(without_nonce(&mined), original);
+}
+
+#[test]
+fn mines_commits_with_an_empty_message() {
+    for location in ["header", "trailer"] {
+        let repo = TestRepo::new(&format!("empty-message-{}", location));
+        repo.git(&[
+            "commit",
+            "-q",
+            "--allow-empty",
+            "--allow-empty-message",
+            "-m",
+            "",
+        ]);
+        let original = repo.cat_commit("HEAD");
+        assert!(original.ends_with(b"\n\n"));
+        repo.power(&["--bits", "8", "--nonce-location", location]);
+        assert_mined(&repo, "HEAD");
+        repo.fsck();
+
+        let mined = repo.cat_commit("HEAD");
+        if location == "header" {
+            assert_eq!(without_nonce(&mined), original);
+        } else {
+            assert!(mined.starts_with(&original) && mined.ends_with(b"\n"));
+            assert_eq!(&mined[original.len()..original.len() + 7], b"Nonce: ");
+        }
+        // Reading it back, through git and through git-power's own repository access, the
+        // message is still empty
+        let message = repo.git(&["log", "-1", "--format=%B"]);
+        assert_eq!(message.is_empty(), location == "header");
+        repo.power(&["verify", "--bits", "8"]);
+    }
+}
+
+#[test]
+fn mines_in_a_linked_worktree() {
+    let repo = TestRepo::new("worktree");
+    repo.commit("Initial commit");
+    repo.git(&["worktree", "add", "-q", "-b", "feature", "feature"]);
+    let worktree = repo.dir.join("feature");
+    repo.git_in(
+        &worktree,
+        &["commit", "-q", "--allow-empty", "-m", "In the worktree"],
+    );
+    let main = repo.rev_parse("main");
+
+    repo.power_in(&worktree, &["--bits", "8"]);
+    // Only the worktree's branch moves, and the main checkout stays where it was
+    let feature = repo.rev_parse("feature");
+    assert!(feature.starts_with("00"), "feature is {}", feature);
+    assert_eq!(repo.git_in(&worktree, &["rev-parse", "HEAD"]), feature);
+    assert_eq!(repo.rev_parse("main"), main);
+    assert_eq!(repo.rev_parse("HEAD"), main);
+    assert_eq!(repo.git(&["symbolic-ref", "HEAD"]), "refs/heads/main");
+    assert_eq!(repo.git_in(&worktree, &["status", "--porcelain"]), "");
+    repo.fsck();
+}
+
+#[test]
+fn mines_from_a_subdirectory() {
+    let repo = TestRepo::new("subdirectory");
+    let nested = repo.dir.join("src").join("nested");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(nested.join("file"), "contents\n").unwrap();
+    repo.git(&["add", "."]);
+    repo.commit("Initial commit");
+
+    repo.power_in(&nested, &["--bits", "8"]);
+    assert_mined(&repo, "HEAD");
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+    repo.fsck();
+}
+
+#[test]
+fn piped_progress_has_a_line_per_update() {
+    let repo = TestRepo::new("progress");
+    repo.commit("Initial commit");
+    // Neither stdout nor stderr is a terminal when captured
+    let output = repo.power(&["--bits", "12"]);
+    let progress = String::from_utf8(output.stderr).unwrap();
+    let stats = String::from_utf8(output.stdout).unwrap();
+    assert!(!progress.contains('\r') && !stats.contains('\r'));
+    assert!(progress.ends_with('\n'));
+    let found: Vec<_> = progress
+        .lines()
+        .filter(|line| line.starts_with("Found "))
+        .collect();
+    assert!(!found.is_empty(), "{}", progress);
+    assert!(found.last().unwrap().contains(&repo.rev_parse("HEAD")));
+}
+
+#[test]
+fn runs_as_a_git_subcommand() {
+    let repo = TestRepo::new("subcommand");
+    repo.commit("Initial commit");
+    let exe = Path::new(env!("CARGO_BIN_EXE_git-power"));
+    assert_eq!(exe.file_stem().unwrap(), "git-power");
+    // git finds git-power by name on the PATH, and passes on every argument after `power`
+    let path = std::env::join_paths(
+        std::iter::once(exe.parent().unwrap().to_path_buf())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+    let git_power = |args: &[&str]| {
+        let output = run(
+            repo.command("git", &repo.dir)
+                .env("PATH", &path)
+                .arg("power")
+                .args(args),
+            None,
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert!(git_power(&["-h"]).starts_with("git-power "));
+    git_power(&["--bits", "8", "--quiet"]);
+    assert_mined(&repo, "HEAD");
+    assert!(git_power(&["verify", "--bits", "8"]).contains("leading zero bits"));
+
+    // `git power --help` is git showing the man page of git-power, which takes `man` to read
+    let man = repo.dir.join("man");
+    std::fs::create_dir_all(man.join("man1")).unwrap();
+    std::fs::write(man.join("man1").join("git-power.1"), git_power(&["man"])).unwrap();
+    if Command::new("man").arg("-w").arg("man").output().is_err() {
+        eprintln!("Skipping `git power --help`, since man isn't available");
+        return;
+    }
+    let output = run(
+        repo.command("git", &repo.dir)
+            .env("PATH", &path)
+            .env("MANPATH", &man)
+            .env("MANPAGER", "cat")
+            .args(["power", "--help"]),
+        None,
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("--bits"));
+}
+
+/// The fields of the flat JSON object `--format json` prints, which has no strings needing
+/// escapes and no nesting, so splitting it up at the commas is enough to parse it.
+fn json_fields(json: &str) -> Vec<(String, String)> {
+    let json = json
+        .trim()
+        .strip_prefix('{')
+        .unwrap()
+        .strip_suffix('}')
+        .unwrap();
+    json.split(',')
+        .map(|field| {
+            let (key, value) = field.split_once(':').unwrap();
+            let key = key.strip_prefix('"').unwrap().strip_suffix('"').unwrap();
+            (key.to_string(), value.trim_matches('"').to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn reports_the_result_as_json() {
+    let repo = TestRepo::new("json");
+    repo.commit("Initial commit");
+    let original = repo.rev_parse("HEAD");
+    let output = repo.power(&["--bits", "8", "--format", "json"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1, "{}", stdout);
+    let fields = json_fields(&stdout);
+    let keys: Vec<_> = fields.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(
+        keys,
+        [
+            "original_oid",
+            "new_oid",
+            "mined",
+            "bits",
+            "attempts",
+            "seconds",
+            "hashrate"
+        ]
+    );
+    let field = |key: &str| &fields.iter().find(|(k, _)| k == key).unwrap().1;
+    assert_eq!(field("original_oid"), &original);
+    assert_eq!(field("new_oid"), &repo.rev_parse("HEAD"));
+    assert_eq!(field("mined"), "true");
+    assert!(field("bits").parse::<u16>().unwrap() >= 8);
+    assert!(field("attempts").parse::<u128>().unwrap() > 0);
+    assert!(field("seconds").parse::<f64>().unwrap() >= 0.0);
+    assert!(field("hashrate").parse::<f64>().unwrap() >= 0.0);
+
+    // Already meeting the target, the commit is left alone, which is still reported
+    let output = repo.power(&["--bits", "8", "--format", "json"]);
+    let fields = json_fields(std::str::from_utf8(&output.stdout).unwrap());
+    let field = |key: &str| &fields.iter().find(|(k, _)| k == key).unwrap().1;
+    assert_eq!(field("mined"), "false");
+    assert_eq!(field("new_oid"), field("original_oid"));
+    assert_eq!(field("attempts"), "0");
+}
+
+#[test]
+fn porcelain_prints_only_the_hash() {
+    let repo = TestRepo::new("porcelain");
+    repo.commit("Initial commit");
+    let original = repo.rev_parse("HEAD");
+
+    let output = repo.power(&["--bits", "8", "--porcelain", "--dry-run"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.len(), 41, "{:?}", stdout);
+    assert!(stdout.starts_with("00") && stdout.ends_with('\n'));
+    assert!(output.stderr.is_empty());
+    assert_eq!(repo.rev_parse("HEAD"), original);
+
+    let output = repo.power(&["--bits", "8", "--porcelain"]);
+    let mined = repo.rev_parse("HEAD");
+    assert_eq!(output.stdout, format!("{}\n", mined).into_bytes());
+    // Already meeting the target, it's the same hash again
+    let output = repo.power(&["--bits", "8", "--porcelain"]);
+    assert_eq!(output.stdout, format!("{}\n", mined).into_bytes());
+    let output = repo.power(&["--bits", "0", "--porcelain"]);
+    assert_eq!(output.stdout, format!("{}\n", mined).into_bytes());
+}
+
+#[test]
+fn stdout_prints_the_object_without_writing_it() {
+    let repo = TestRepo::new("stdout");
+    repo.commit("Initial commit");
+    let original = repo.rev_parse("HEAD");
+
+    let output = repo.power(&["--bits", "8", "--stdout"]);
+    assert_eq!(repo.rev_parse("HEAD"), original);
+    let hashed = run(
+        repo.command("git", &repo.dir)
+            .args(["hash-object", "-t", "commit", "--stdin"]),
+        Some(&output.stdout),
+    );
+    let id = String::from_utf8(hashed.stdout).unwrap();
+    assert!(id.starts_with("00"), "{}", id);
+    // Not having been written, the object isn't in the repository
+    let exists = repo
+        .command("git", &repo.dir)
+        .args(["cat-file", "-e", id.trim()])
+        .status()
+        .unwrap();
+    assert!(!exists.success());
+
+    // With nothing to mine, the object comes out as it is
+    let output = repo.power(&["--bits", "0", "--stdout"]);
+    assert_eq!(output.stdout, repo.cat_commit("HEAD"));
+}
+
+#[test]
+fn zero_bits_leaves_the_commit_alone() {
+    let repo = TestRepo::new("zero-bits");
+    repo.commit("Initial commit");
+    let head = repo.rev_parse("HEAD");
+    let reflog = repo.git(&["reflog", "show", "--format=%H %gs"]);
+    repo.power(&["--bits", "0"]);
+    assert_eq!(repo.rev_parse("HEAD"), head);
+    // Not even an update to the same id is recorded
+    assert_eq!(repo.git(&["reflog", "show", "--format=%H %gs"]), reflog);
+    assert_eq!(repo.git(&["reflog", "show", "--format=%H", "main"]), head);
+}
+
+#[test]
+fn the_written_object_has_the_mined_id() {
+    let repo = TestRepo::new("hash-object");
+    repo.commit("Initial commit");
+    let output = repo.power(&["--bits", "8", "--porcelain"]);
+    let head = repo.rev_parse("HEAD");
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), head);
+    assert!(head.starts_with("00"), "HEAD is {}", head);
+    // git arrives at the id git-power computed, hashing the contents itself
+    let hashed = run(
+        repo.command("git", &repo.dir)
+            .args(["hash-object", "-t", "commit", "--stdin"]),
+        Some(&repo.cat_commit("HEAD")),
+    );
+    assert_eq!(String::from_utf8(hashed.stdout).unwrap().trim(), head);
+}
+
+#[test]
+fn a_detached_head_stays_detached() {
+    let repo = TestRepo::new("detached");
+    repo.commit("Initial commit");
+    repo.git(&["checkout", "-q", "--detach"]);
+    repo.commit("Detached");
+    let main = repo.rev_parse("main");
+
+    let output = repo.power(&["--bits", "8", "--porcelain"]);
+    let mined = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    assert_eq!(repo.rev_parse("HEAD"), mined);
+    assert!(mined.starts_with("00"), "HEAD is {}", mined);
+    // HEAD holds the id itself rather than naming a branch, and no branch moved
+    let head = std::fs::read_to_string(repo.dir.join(".git").join("HEAD")).unwrap();
+    assert_eq!(head.trim(), mined);
+    assert_eq!(repo.rev_parse("main"), main);
+    assert_eq!(
+        repo.git(&["for-each-ref", "--format=%(refname)"]),
+        "refs/heads/main"
+    );
+    repo.fsck();
+}
+
+#[test]
+fn respects_git_dir_and_git_work_tree() {
+    let repo = TestRepo::new("git-dir");
+    repo.commit("Initial commit");
+    // Run from within another repository, which is the one found without the variables
+    let other = TestRepo::new("git-dir-other");
+    other.commit("Elsewhere");
+    let elsewhere = other.rev_parse("HEAD");
+
+    run(
+        repo.command(env!("CARGO_BIN_EXE_git-power"), &other.dir)
+            .env("GIT_DIR", repo.dir.join(".git"))
+            .env("GIT_WORK_TREE", &repo.dir)
+            .args(["--threads", "2", "--bits", "8"]),
+        None,
+    );
+    assert_mined(&repo, "HEAD");
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+    repo.fsck();
+    assert_eq!(other.rev_parse("HEAD"), elsewhere);
+}
+
+#[test]
+fn mines_in_a_sha256_repository() {
+    let repo = TestRepo::init("sha256", &["--object-format=sha256"]);
+    repo.commit("Initial commit");
+    let original = repo.cat_commit("HEAD");
+    repo.power(&["--bits", "8"]);
+    let head = repo.rev_parse("HEAD");
+    assert_eq!(head.len(), 64, "HEAD is {}", head);
+    assert!(head.starts_with("00"), "HEAD is {}", head);
+    assert_eq!(without_nonce(&repo.cat_commit("HEAD")), original);
+    repo.fsck();
+}
+
+#[test]
+fn detects_the_object_format() {
+    for (format, len) in [("sha1", 40), ("sha256", 64)] {
+        let repo = TestRepo::init(
+            &format!("object-format-{}", format),
+            &["--object-format", format],
+        );
+        repo.commit("Initial commit");
+        let output = repo.power(&["--bits", "4", "--porcelain"]);
+        let id = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(id.trim(), repo.rev_parse("HEAD"));
+        assert_eq!(id.trim().len(), len, "{}", id);
+        repo.fsck();
+    }
+}
+
+#[test]
+fn zeros_count_hex_digits() {
+    let repo = TestRepo::new("zeros");
+    repo.commit("Initial commit");
+    let output = repo.power(&["--zeros", "2"]);
+    assert_mined(&repo, "HEAD");
+    // Progress is counted in digits as well
+    let progress = String::from_utf8(output.stderr).unwrap();
+    assert!(progress.contains("/2 leading zero digits"), "{}", progress);
+    repo.power(&["verify", "--bits", "8"]);
+}
+
+#[test]
+fn max_time_settles_for_the_closest_hash() {
+    let repo = TestRepo::new("max-time");
+    repo.commit("Initial commit");
+    let original = repo.rev_parse("HEAD");
+    let output = repo.power(&["--bits", "160", "--max-time", "0.5"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Out of time"), "{}", stdout);
+    assert_ne!(repo.rev_parse("HEAD"), original);
+    repo.fsck();
+
+    // Pinned bytes can't be partially met, so no hash comes any closer than another, and
+    // there's nothing to settle for
+    let mined = repo.rev_parse("HEAD");
+    let reflog = repo.git(&["reflog", "show", "--format=%H"]);
+    let pins = [
+        "--byte", "0=00", "--byte", "1=00", "--byte", "2=00", "--byte", "3=00",
+    ];
+    let output = repo.power_fails(&[&pins[..], &["--max-time", "0.5"]].concat());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("in time"), "{}", stderr);
+    assert_eq!(repo.rev_parse("HEAD"), mined);
+    assert_eq!(repo.git(&["reflog", "show", "--format=%H"]), reflog);
+}
+
+#[test]
+fn verify_exits_with_whether_the_target_is_met() {
+    let repo = TestRepo::new("verify");
+    repo.commit("First");
+    repo.commit("Second");
+    repo.power(&["--bits", "8"]);
+    let head = repo.rev_parse("HEAD");
+
+    let output = repo.power(&["verify", "--bits", "8"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(&format!("{} has ", head)), "{}", stdout);
+    let output = repo.power_fails(&["verify", "HEAD~1", "--bits", "8"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("does not meet the target"), "{}", stderr);
+    // Nothing gets mined either way
+    assert_eq!(repo.rev_parse("HEAD"), head);
+}
+
+#[test]
+fn estimate_leaves_the_commit_alone() {
+    let repo = TestRepo::new("estimate");
+    repo.commit("Initial commit");
+    let head = repo.rev_parse("HEAD");
+    let output = repo.power(&["--bits", "20", "--estimate"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines[0], "Expected attempts: 1048576 (2^20.0)",
+        "{}",
+        stdout
+    );
+    assert!(lines[1].starts_with("Measured hashrate: "), "{}", stdout);
+    assert!(lines[2].starts_with("Expected time: "), "{}", stdout);
+    assert_eq!(repo.rev_parse("HEAD"), head);
+}
+
+#[test]
+fn bench_reports_every_thread() {
+    let repo = TestRepo::new("bench");
+    for format in ["sha1", "sha256"] {
+        let output = run(
+            repo.command(env!("CARGO_BIN_EXE_git-power"), &repo.dir)
+                .args(["--object-format", format, "bench"])
+                .args(["--threads", "3", "--seconds", "0.2"]),
+            None,
+        );
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<_> = stdout.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", stdout);
+        assert!(lines[0].starts_with("Hashing for 0.2 seconds with 3 threads"));
+        for (i, line) in lines[1..4].iter().enumerate() {
+            let rate = line.strip_prefix(&format!("Thread {}: ", i)).unwrap();
+            assert!(rate.strip_suffix("MH/s").unwrap().parse::<f64>().unwrap() > 0.0);
+        }
+        assert!(lines[4].starts_with("Total: ") && lines[4].ends_with("MH/s"));
+    }
+    // It hashes a made-up commit, not needing one of its own
+    assert_eq!(repo.git(&["rev-list", "--all"]), "");
+}
+
+#[test]
+fn rewrites_the_descendants_of_a_mined_commit() {
+    let repo = TestRepo::new("rewrite-descendants");
+    repo.commit("Initial commit");
+    repo.commit("Second commit");
+    repo.commit("Third commit");
+    let third = repo.cat_commit("HEAD");
+    let output = repo.power_fails(&["--bits", "8", "HEAD~1"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pass --rewrite-descendants"), "{}", stderr);
+
+    repo.power(&["--bits", "8", "--rewrite-descendants", "HEAD~1"]);
+    assert_mined(&repo, "HEAD~1");
+    assert_eq!(repo.rev_parse("HEAD^"), repo.rev_parse("HEAD~1"));
+    assert_eq!(repo.git(&["log", "-1", "--format=%s"]), "Third commit");
+    // Only the parent of the commit on top changes
+    let rewritten = repo.cat_commit("HEAD");
+    assert_ne!(rewritten, third);
+    assert_eq!(lines_after_parent(&rewritten), lines_after_parent(&third));
+    repo.fsck();
+}
+
+fn lines_after_parent(commit: &[u8]) -> &[u8] {
+    let start = find(commit, b"\nparent ").unwrap() + 1;
+    &commit[start + find(&commit[start..], b"\n").unwrap()..]
+}
+
+#[test]
+fn refuses_to_rewrite_signed_descendants() {
+    let repo = TestRepo::new("signed-descendants");
+    let Some(signing_key) = repo.ssh_signing_key() else {
+        eprintln!("Skipping the signed descendants test, since ssh-keygen isn't available");
+        return;
+    };
+    repo.commit("Initial commit");
+    repo.git(&[
+        "-c",
+        "gpg.format=ssh",
+        "-c",
+        &signing_key,
+        "commit",
+        "-q",
+        "-S",
+        "--allow-empty",
+        "-m",
+        "Signed",
+    ]);
+    let head = repo.rev_parse("HEAD");
+    let output = repo.power_fails(&["--bits", "8", "--rewrite-descendants", "HEAD~1"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("Cannot rewrite {}, since it's signed", head)),
+        "{}",
+        stderr
+    );
+    // Nothing was mined, let alone written
+    assert!(!stderr.contains("Found"), "{}", stderr);
+    assert_eq!(repo.rev_parse("HEAD"), head);
+}
+
+#[test]
+fn defaults_come_from_home_then_the_repo_then_the_environment() {
+    let repo = TestRepo::new("defaults");
+    repo.commit("Initial commit");
+    let home = repo.dir.join("home");
+    std::fs::create_dir(&home).unwrap();
+    // What's expected and how many threads are measured with, as --estimate reports them
+    let estimate = |env: &[(&str, &str)], args: &[&str]| {
+        let output = run(
+            repo.command(env!("CARGO_BIN_EXE_git-power"), &repo.dir)
+                .env("HOME", &home)
+                .envs(env.iter().copied())
+                .arg("--estimate")
+                .args(args),
+            None,
+        );
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<_> = stdout.lines().collect();
+        let threads = lines[1].split(" with ").nth(1).unwrap();
+        (
+            lines[0].to_string(),
+            threads.split(" using ").next().unwrap().to_string(),
+        )
+    };
+    let expected = |bits: u16, threads: &str| {
+        (
+            format!("Expected attempts: {} (2^{}.0)", 1u64 << bits, bits),
+            threads.to_string(),
+        )
+    };
+
+    std::fs::write(home.join(".git-power.toml"), "bits = 6\nthreads = 1\n").unwrap();
+    assert_eq!(estimate(&[], &[]), expected(6, "1 threads"));
+    std::fs::write(
+        repo.dir.join(".git-power.toml"),
+        "bits = 7 # closer to home\n",
+    )
+    .unwrap();
+    assert_eq!(estimate(&[], &[]), expected(7, "1 threads"));
+    let env = [("GIT_POWER_BITS", "9"), ("GIT_POWER_THREADS", "2")];
+    assert_eq!(estimate(&env, &[]), expected(9, "2 threads"));
+    assert_eq!(
+        estimate(&env, &["--bits", "10", "--threads", "3"]),
+        expected(10, "3 threads")
+    );
+}
+
+#[test]
+fn the_post_commit_hook_mines_every_commit() {
+    let repo = TestRepo::new("post-commit-hook");
+    let output = repo.power(&["hook", "install", "--bits", "8"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Installed "), "{}", stdout);
+    repo.commit("Initial commit");
+    assert_mined(&repo, "HEAD");
+    repo.commit("Second commit");
+    assert_mined(&repo, "HEAD");
+    assert_mined(&repo, "HEAD~1");
+    // The hook didn't run again on the mined commits, which would have left more behind
+    assert_eq!(repo.git(&["rev-list", "--count", "HEAD"]), "2");
+    repo.fsck();
+
+    let output = repo.power_fails(&["hook", "install", "--bits", "12"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pass --force to replace it"), "{}", stderr);
+    repo.power(&["hook", "install", "--bits", "12", "--force"]);
+}
+
+#[test]
+fn the_pre_push_hook_rejects_unmined_commits() {
+    let repo = TestRepo::new("pre-push-hook");
+    let remote = repo.dir.join("remote.git");
+    repo.git(&["init", "-q", "--bare", remote.to_str().unwrap()]);
+    repo.git(&["remote", "add", "origin", remote.to_str().unwrap()]);
+    repo.power(&["hook", "install", "--pre-push", "--bits", "8"]);
+
+    repo.commit("Initial commit");
+    repo.power(&["--bits", "8"]);
+    repo.commit("Unmined commit");
+    let push = |repo: &TestRepo| {
+        repo.command("git", &repo.dir)
+            .args(["push", "-q", "origin", "main"])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = push(&repo);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("does not meet the target"), "{}", stderr);
+    assert_eq!(repo.git_in(&remote, &["for-each-ref"]), "");
+
+    repo.power(&["--bits", "8"]);
+    let output = push(&repo);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        repo.git_in(&remote, &["rev-parse", "main"]),
+        repo.rev_parse("HEAD")
+    );
+}
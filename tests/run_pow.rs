@@ -0,0 +1,71 @@
+// Mining through the public API alone, the way a crate embedding git-power would.
+
+use git_power::pool::Pool;
+use git_power::target::Target;
+use git_power::{
+    num_leading_zero_bits, run_pow, CommitBuffer, MineOptions, NonceLocation, Outcome,
+    ProgressSink, Sha1, Stats,
+};
+use sha1::Digest;
+use std::sync::Mutex;
+
+const COMMIT: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+    author A U Thor <author@example.com> 1700000000 +0000\n\
+    committer A U Thor <author@example.com> 1700000000 +0000\n\
+    \n\
+    Initial commit\n";
+
+/// How mining ended, as told to `on_done`.
+type Done = (Outcome, u128, Option<Vec<u8>>);
+
+/// Keeps everything it's told, for checking afterwards.
+#[derive(Default)]
+struct Recorder {
+    improvements: Mutex<Vec<(Vec<u8>, u16)>>,
+    done: Mutex<Option<Done>>,
+}
+
+impl ProgressSink for Recorder {
+    fn on_improvement(&self, hash: &[u8], zeros: u16) {
+        self.improvements
+            .lock()
+            .unwrap()
+            .push((hash.to_vec(), zeros));
+    }
+
+    fn on_done(&self, stats: &Stats) {
+        let done = (
+            stats.outcome,
+            stats.attempts,
+            stats.hash.map(<[u8]>::to_vec),
+        );
+        *self.done.lock().unwrap() = Some(done);
+    }
+}
+
+#[test]
+fn mines_through_the_public_api() {
+    let pool = Pool::new(2, false).unwrap();
+    let buf = CommitBuffer::new(COMMIT, NonceLocation::Header).unwrap();
+    let target = Target {
+        bits: 14,
+        ..Target::default()
+    };
+    let sink = Recorder::default();
+    let mined = run_pow::<Sha1>(&pool, buf, target, &MineOptions::default(), &sink).unwrap();
+
+    // The hash is the id git gives the mined object
+    let data = mined.commit.data();
+    let mut object = format!("commit {}\0", data.len()).into_bytes();
+    object.extend_from_slice(data);
+    assert_eq!(mined.hash, Sha1::digest(&object).to_vec());
+    assert!(num_leading_zero_bits(&mined.hash) >= 14);
+
+    let improvements = sink.improvements.into_inner().unwrap();
+    assert!(improvements.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    assert_eq!(improvements.last().unwrap().0, mined.hash);
+    let (outcome, attempts, hash) = sink.done.into_inner().unwrap().unwrap();
+    assert_eq!(outcome, Outcome::Found);
+    assert_eq!(attempts, mined.attempts);
+    assert_eq!(hash, Some(mined.hash));
+}
@@ -0,0 +1,107 @@
+// Mining commit objects piped in with --stdin, which needs neither a repository nor git itself,
+// whichever way the binary was built to access repositories.
+
+use sha1::{Digest, Sha1};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const COMMIT: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+    author A U Thor <author@example.com> 1700000000 +0000\n\
+    committer A U Thor <author@example.com> 1700000000 +0000\n\
+    \n\
+    Initial commit\n";
+
+/// A directory outside of any repository, removed again once the test is done with it.
+struct EmptyDir(PathBuf);
+
+impl EmptyDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "git-power-stdin-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    /// Runs git-power on `stdin`, with no git on the PATH to fall back on.
+    fn power(&self, args: &[&str], stdin: &[u8]) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_git-power"))
+            .current_dir(&self.0)
+            .env("PATH", "")
+            .env("HOME", &self.0)
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE")
+            .env_remove("GIT_POWER_BITS")
+            .env_remove("GIT_POWER_THREADS")
+            .args(["--threads", "2", "--stdin"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        child.wait_with_output().unwrap()
+    }
+}
+
+impl Drop for EmptyDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The id git would give a commit object.
+fn commit_id(data: &[u8]) -> Vec<u8> {
+    let mut object = format!("commit {}\0", data.len()).into_bytes();
+    object.extend_from_slice(data);
+    Sha1::digest(&object).to_vec()
+}
+
+#[test]
+fn mines_an_object_piped_in_without_a_repository() {
+    let dir = EmptyDir::new("mine");
+    let output = dir.power(&["--bits", "12"], COMMIT);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mined = output.stdout;
+    let id = commit_id(&mined);
+    assert!(id[0] == 0 && id[1] >> 4 == 0, "{:02x?}", id);
+    // The nonce header comes right after the committer
+    let headers_end = COMMIT.windows(2).position(|w| w == b"\n\n").unwrap() + 1;
+    assert_eq!(mined[..headers_end], COMMIT[..headers_end]);
+    assert!(mined[headers_end..].starts_with(b"nonce "));
+    let nonce_end = headers_end
+        + mined[headers_end..]
+            .iter()
+            .position(|&c| c == b'\n')
+            .unwrap();
+    assert_eq!(mined[nonce_end + 1..], COMMIT[headers_end..]);
+    // Nothing was written anywhere
+    assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 0);
+}
+
+#[test]
+fn a_trivial_target_passes_the_object_through() {
+    let dir = EmptyDir::new("trivial");
+    let output = dir.power(&["--bits", "0"], COMMIT);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, COMMIT);
+}
+
+#[test]
+fn rejects_what_isnt_a_commit_object() {
+    let dir = EmptyDir::new("invalid");
+    let output = dir.power(&["--bits", "8"], b"not a commit\n");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Malformed commit"), "{}", stderr);
+    assert!(output.stdout.is_empty());
+}